@@ -0,0 +1,301 @@
+//! Server-wide bans
+//!
+//! K-lines ban `user@host` masks and are checked when a client registers,
+//! D-lines ban address ranges and are checked when a connection is
+//! accepted. Without server links G-lines and Z-lines are the same as
+//! K-lines and D-lines.
+//!
+//! The bans are stored in the ban file, one ban per line:
+//!
+//! ```text
+//! K <user@host> <set at> <expires at> <set by> :<reason>
+//! D <address range> <set at> <expires at> <set by> :<reason>
+//! ```
+//!
+//! Times are UNIX timestamps, bans that expire at 0 are permanent.
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+
+use misc;
+use net::Cidr;
+
+/// Server-wide ban
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ban<T> {
+    /// Banned mask or address range
+    pub target: T,
+    /// Reason that is shown to banned users
+    pub reason: String,
+    /// Nick of the operator that set the ban
+    pub set_by: String,
+    /// Time the ban has been set
+    pub set_at: i64,
+    /// Time the ban expires, `None` for permanent bans
+    pub expires: Option<i64>
+}
+
+impl<T> Ban<T> {
+    /// Checks whether the ban expired at the time `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+}
+
+/// K-line, bans a `user@host` mask
+pub type KLine = Ban<String>;
+/// D-line, bans an address range
+pub type DLine = Ban<Cidr>;
+
+/// Normalizes a K-line mask to the form `user@host`
+///
+/// A mask without `@` bans all users of the host. Returns `None` for masks
+/// that would ban everybody or contain invalid characters.
+pub fn kline_mask(mask: &str) -> Option<String> {
+    let mask = if mask.contains('@') {
+        mask.to_lowercase()
+    } else {
+        format!("*@{}", mask.to_lowercase())
+    };
+    let valid = {
+        let mut parts = mask.splitn(2, '@');
+        match (parts.next(), parts.next()) {
+            (Some(user), Some(host)) => {
+                !user.is_empty() && !host.is_empty() && !host.contains('@')
+                && host.chars().any(|c| c != '*' && c != '?' && c != '.')
+            },
+            _ => false
+        }
+    };
+    if valid && !mask.contains(|c: char| c == '!' || c == ' ') {
+        Some(mask)
+    } else {
+        None
+    }
+}
+
+/// Active K-lines and D-lines
+#[derive(Debug)]
+pub struct BanList {
+    klines: Vec<KLine>,
+    dlines: Vec<DLine>,
+    path: Option<String>
+}
+
+impl BanList {
+    /// Creates an empty ban list that is not stored
+    pub fn new() -> BanList {
+        BanList {
+            klines: Vec::new(),
+            dlines: Vec::new(),
+            path: None
+        }
+    }
+
+    /// Loads the bans from the ban file, a missing file is empty
+    ///
+    /// Changes are written back to the same file.
+    pub fn load(path: &str) -> io::Result<BanList> {
+        let mut bans = BanList::new();
+        bans.path = Some(path.to_string());
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(bans),
+            Err(err) => return Err(err)
+        };
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = try!(line);
+            if line.trim().is_empty() {
+                continue
+            }
+            if !bans.parse_line(&line) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "{}, line {}: invalid ban", path, i + 1
+                )))
+            }
+        }
+        Ok(bans)
+    }
+
+    fn parse_line(&mut self, line: &str) -> bool {
+        let (fields, reason) = match line.find(" :") {
+            Some(i) => (&line[..i], &line[i + 2..]),
+            None => return false
+        };
+        let fields: Vec<&str> = fields.split(' ').collect();
+        if fields.len() != 5 {
+            return false
+        }
+        let (set_at, expires) = match (fields[2].parse::<i64>(), fields[3].parse::<i64>()) {
+            (Ok(set_at), Ok(expires)) => (set_at, if expires > 0 { Some(expires) } else { None }),
+            _ => return false
+        };
+        match fields[0] {
+            "K" => match kline_mask(fields[1]) {
+                Some(mask) => self.klines.push(Ban {
+                    target: mask,
+                    reason: reason.to_string(),
+                    set_by: fields[4].to_string(),
+                    set_at: set_at,
+                    expires: expires
+                }),
+                None => return false
+            },
+            "D" => match Cidr::parse(fields[1]) {
+                Some(range) => self.dlines.push(Ban {
+                    target: range,
+                    reason: reason.to_string(),
+                    set_by: fields[4].to_string(),
+                    set_at: set_at,
+                    expires: expires
+                }),
+                None => return false
+            },
+            _ => return false
+        }
+        true
+    }
+
+    /// Writes the bans to the ban file
+    ///
+    /// The file is replaced at once such that it is never left half written.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(())
+        };
+        let tmp = format!("{}.tmp", path);
+        {
+            let mut file = try!(File::create(&tmp));
+            for ban in self.klines.iter() {
+                try!(write!(file, "K {} {} {} {} :{}\n",
+                    ban.target, ban.set_at, ban.expires.unwrap_or(0), ban.set_by, ban.reason
+                ));
+            }
+            for ban in self.dlines.iter() {
+                try!(write!(file, "D {} {} {} {} :{}\n",
+                    ban.target, ban.set_at, ban.expires.unwrap_or(0), ban.set_by, ban.reason
+                ));
+            }
+            try!(file.sync_all());
+        }
+        fs::rename(&tmp, path)
+    }
+
+    /// Returns the K-line that matches a user
+    ///
+    /// `hosts` are the host name and the address of the user.
+    pub fn kline_for(&self, user: &str, hosts: &[&str], now: i64) -> Option<&KLine> {
+        let names: Vec<String> = hosts.iter().map(
+            |host| format!("{}@{}", user, host).to_lowercase()
+        ).collect();
+        self.klines.iter().find(|ban| !ban.is_expired(now) && names.iter().any(
+            |name| misc::match_mask(&ban.target, name)
+        ))
+    }
+
+    /// Returns the D-line that matches an address
+    pub fn dline_for(&self, ip: IpAddr, now: i64) -> Option<&DLine> {
+        self.dlines.iter().find(|ban| !ban.is_expired(now) && ban.target.contains(ip))
+    }
+
+    /// Adds a K-line, an existing K-line of the same mask is replaced
+    pub fn add_kline(&mut self, ban: KLine) {
+        self.klines.retain(|other| other.target != ban.target);
+        self.klines.push(ban)
+    }
+
+    /// Removes the K-line of the mask
+    pub fn remove_kline(&mut self, mask: &str) -> bool {
+        let len = self.klines.len();
+        self.klines.retain(|ban| ban.target != mask);
+        self.klines.len() != len
+    }
+
+    /// Adds a D-line, an existing D-line of the same range is replaced
+    pub fn add_dline(&mut self, ban: DLine) {
+        self.dlines.retain(|other| other.target != ban.target);
+        self.dlines.push(ban)
+    }
+
+    /// Removes the D-line of the range
+    pub fn remove_dline(&mut self, range: Cidr) -> bool {
+        let len = self.dlines.len();
+        self.dlines.retain(|ban| ban.target != range);
+        self.dlines.len() != len
+    }
+
+    /// Drops the expired bans, returns whether any ban expired
+    pub fn expire(&mut self, now: i64) -> bool {
+        let len = self.klines.len() + self.dlines.len();
+        self.klines.retain(|ban| !ban.is_expired(now));
+        self.dlines.retain(|ban| !ban.is_expired(now));
+        self.klines.len() + self.dlines.len() != len
+    }
+
+    /// Getter for the K-lines
+    pub fn klines(&self) -> &[KLine] {
+        &self.klines
+    }
+
+    /// Getter for the D-lines
+    pub fn dlines(&self) -> &[DLine] {
+        &self.dlines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use net::Cidr;
+    use super::{Ban, BanList, kline_mask};
+
+    fn ban<T>(target: T, expires: Option<i64>) -> Ban<T> {
+        Ban {
+            target: target,
+            reason: "spam bot".to_string(),
+            set_by: "admin".to_string(),
+            set_at: 100,
+            expires: expires
+        }
+    }
+
+    #[test]
+    fn ban_matching() {
+        assert_eq!(kline_mask("*@Example.COM"), Some("*@example.com".to_string()));
+        assert_eq!(kline_mask("192.0.2.*"), Some("*@192.0.2.*".to_string()));
+        assert_eq!(kline_mask("*@*"), None);
+        assert_eq!(kline_mask("a@b@c"), None);
+        let mut bans = BanList::new();
+        bans.add_kline(ban("~*@*.example.com".to_string(), None));
+        bans.add_kline(ban("*@192.0.2.*".to_string(), Some(200)));
+        bans.add_dline(ban(Cidr::parse("198.51.100.0/24").unwrap(), None));
+        assert!(bans.kline_for("~bot", &["host.Example.com", "203.0.113.1"], 150).is_some());
+        assert!(bans.kline_for("bot", &["host.example.com", "203.0.113.1"], 150).is_none());
+        assert!(bans.kline_for("bot", &["host.example.org", "192.0.2.7"], 150).is_some());
+        assert!(bans.kline_for("bot", &["host.example.org", "192.0.2.7"], 200).is_none());
+        assert!(bans.dline_for("198.51.100.7".parse().unwrap(), 150).is_some());
+        assert!(bans.dline_for("198.51.101.7".parse().unwrap(), 150).is_none());
+        assert!(bans.expire(200));
+        assert_eq!(bans.klines().len(), 1);
+        assert!(bans.remove_kline("~*@*.example.com"));
+        assert!(!bans.remove_kline("~*@*.example.com"));
+        assert!(bans.remove_dline(Cidr::parse("198.51.100.0/24").unwrap()));
+    }
+
+    #[test]
+    fn ban_file() {
+        let path = env::temp_dir().join("rauta-test-bans");
+        let path = path.to_str().unwrap();
+        let mut bans = BanList::load(path).unwrap();
+        bans.add_kline(ban("*@example.com".to_string(), None));
+        bans.add_dline(ban(Cidr::parse("2001:db8::/32").unwrap(), Some(300)));
+        bans.save().unwrap();
+        let loaded = BanList::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.klines(), bans.klines());
+        assert_eq!(loaded.dlines(), bans.dlines());
+    }
+}
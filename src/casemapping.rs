@@ -0,0 +1,104 @@
+//! Case mapping of nicknames and channel names
+//!
+//! Nicknames and channel names are compared case insensitively. The maps
+//! of the server and the channels are keyed by the lower case form of the
+//! name as returned by `lower` while the original spelling is kept for
+//! display.
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+/// Case mappings as advertised with `CASEMAPPING`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CaseMapping {
+    /// Only A-Z and a-z are equivalent
+    Ascii,
+    /// Like `Ascii` plus `[]\~` and `{}|^`
+    Rfc1459,
+    /// Like `Ascii` plus `[]\` and `{}|`
+    StrictRfc1459,
+}
+
+/// Case mapping used when none is configured
+pub const DEFAULT: CaseMapping = CaseMapping::Rfc1459;
+
+// 0 means the default mapping
+static CURRENT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+impl CaseMapping {
+    /// Parses the name of a case mapping
+    pub fn from_name(name: &str) -> Option<CaseMapping> {
+        match name {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            _ => None
+        }
+    }
+
+    /// Name of the case mapping
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459",
+        }
+    }
+
+    /// Converts a single character to lower case
+    pub fn lower_char(&self, c: char) -> char {
+        use self::CaseMapping::*;
+        match (*self, c) {
+            (_, 'A'...'Z') => ((c as u8) + 32) as char,
+            (Rfc1459, '[') | (StrictRfc1459, '[') => '{',
+            (Rfc1459, ']') | (StrictRfc1459, ']') => '}',
+            (Rfc1459, '\\') | (StrictRfc1459, '\\') => '|',
+            (Rfc1459, '~') => '^',
+            _ => c
+        }
+    }
+
+    /// Converts a name to lower case
+    pub fn lower(&self, name: &str) -> String {
+        name.chars().map(|c| self.lower_char(c)).collect()
+    }
+}
+
+/// Sets the case mapping used by the server
+pub fn set(mapping: CaseMapping) {
+    CURRENT.store(mapping as usize + 1, Ordering::Relaxed)
+}
+
+/// Returns the case mapping used by the server
+pub fn current() -> CaseMapping {
+    match CURRENT.load(Ordering::Relaxed) {
+        1 => CaseMapping::Ascii,
+        2 => CaseMapping::Rfc1459,
+        3 => CaseMapping::StrictRfc1459,
+        _ => DEFAULT
+    }
+}
+
+/// Converts a name to lower case using the current case mapping
+pub fn lower(name: &str) -> String {
+    current().lower(name)
+}
+
+/// Compares two names using the current case mapping
+pub fn eq(a: &str, b: &str) -> bool {
+    let mapping = current();
+    a.len() == b.len() && a.chars().zip(b.chars()).all(
+        |(a, b)| mapping.lower_char(a) == mapping.lower_char(b)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseMapping::*;
+
+    #[test]
+    fn case_mappings() {
+        assert_eq!(Ascii.lower("Foo[]\\~"), "foo[]\\~");
+        assert_eq!(Rfc1459.lower("Foo[]\\~"), "foo{}|^");
+        assert_eq!(StrictRfc1459.lower("Foo[]\\~"), "foo{}|~");
+        assert_eq!(Rfc1459.lower("#Rust"), "#rust");
+    }
+}
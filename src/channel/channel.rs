@@ -5,11 +5,14 @@ use std::collections::{HashMap, HashSet};
 use std::collections::hash_map;
 use std::sync::mpsc::{self, Sender, channel};
 use std::sync::Arc;
+use std::mem;
 use std::thread::spawn;
 
 use mio;
+use time;
 
 use server;
+use casemapping;
 use protocol::ResponseCode;
 use user::HostMask;
 use client::{ClientId, Client};
@@ -59,11 +62,15 @@ pub enum Event {
 pub struct Channel {
     name: String,
     topic: String,
+    topic_time: Option<i64>,
+    created: i64,
     password: Option<Vec<u8>>,
     flags: Flags,
     limit: Option<usize>,
+    /// Members keyed by the lower case nick
     members: HashMap<String, Member>,
     invite_list: HashSet<ClientId>,
+    /// Lower case nicks of the members
     nicknames: HashMap<ClientId, String>,
     ban_masks: HashSet<HostMask>,
     except_masks: HashSet<HostMask>,
@@ -75,6 +82,8 @@ impl Channel {
         Channel {
             name: name,
             topic: "".to_string(),
+            topic_time: None,
+            created: time::get_time().sec,
             password: None,
             flags: HashSet::new(),
             limit: None,
@@ -120,7 +129,18 @@ impl Channel {
     
     /// Setter for topic
     pub fn set_topic(&mut self, topic: String) {
-        self.topic = topic
+        self.topic = topic;
+        self.topic_time = Some(time::get_time().sec)
+    }
+
+    /// Getter for the time the topic was set (seconds since the epoch)
+    pub fn topic_time(&self) -> Option<i64> {
+        self.topic_time
+    }
+
+    /// Getter for the creation time (seconds since the epoch)
+    pub fn created(&self) -> i64 {
+        self.created
     }
     
     /// Getter for the user limit
@@ -147,6 +167,11 @@ impl Channel {
         self.has_flag(ChannelMode::Secret)
     }
 
+    /// Queries whether the channel is private
+    pub fn is_private(&self) -> bool {
+        self.has_flag(ChannelMode::Private)
+    }
+
     /// Queries whether the channel is invite only
     pub fn is_invite_only(&self) -> bool {
         self.has_flag(ChannelMode::InviteOnly)
@@ -190,11 +215,11 @@ impl Channel {
     }
     
     pub fn member_with_nick(&self, nick: &String) -> Option<&Member> {
-        self.members.get(nick)
+        self.members.get(&casemapping::lower(nick))
     }
     
     pub fn mut_member_with_nick(&mut self, nick: &String) -> Option<&mut Member> {
-        self.members.get_mut(nick)
+        self.members.get_mut(&casemapping::lower(nick))
     }
 
     /// Adds a client to the invite list after it has been invited
@@ -289,12 +314,31 @@ impl Channel {
         if self.member_with_id(member.id()).is_some() {
             false // member already in channel
         } else {
-            self.nicknames.insert(member.id(), member.nick().to_string());
-            self.members.insert(member.nick().to_string(), member);
+            let key = casemapping::lower(member.nick());
+            self.nicknames.insert(member.id(), key.clone());
+            self.members.insert(key, member);
             true
         }
     }
     
+    /// Renames a member after a nick change
+    pub fn rename_member(&mut self, id: &ClientId, nick: String, mask: HostMask) -> bool {
+        let key = casemapping::lower(&nick);
+        let old = match self.nicknames.get_mut(id) {
+            Some(old) => mem::replace(old, key.clone()),
+            None => return false
+        };
+        match self.members.remove(&old) {
+            Some(mut member) => {
+                member.set_nick(nick);
+                member.set_mask(mask);
+                self.members.insert(key, member);
+                true
+            },
+            None => false
+        }
+    }
+    
     /// Adds a member to the channel
     pub fn remove_member(&mut self, id: &ClientId) -> bool {
         let nick = { match self.nicknames.get(id) {
@@ -328,11 +372,15 @@ impl Channel {
         if self.has_flag(ChannelMode::Secret) && !self.is_member(client) {
             return
         }
+        let is_member = self.is_member(client);
         let sender = self.prefixed_list_sender(
             client, ResponseCode::RPL_NAMREPLY, ResponseCode::RPL_ENDOFNAMES, Some("=")
         );
         for member in self.members() {
-            sender.feed_item(member.decorated_nick())
+            // Invisible users are only visible to other members
+            if is_member || !member.is_invisible() {
+                sender.feed_item(member.decorated_nick())
+            }
         }
     }
 
@@ -2,9 +2,9 @@ use std::collections::{HashSet};
 
 use client_io;
 use client::{ClientId, Client, MessageOrigin};
-use user::HostMask;
+use user::{HostMask, UserMode};
 use protocol::{Command, ResponseCode};
-use super::{Flags, ChannelMode};
+use super::{Flags, ChannelMode, PREFIXES};
 use super::ChannelMode::{OperatorPrivilege, VoicePrivilege};
 
 /// Represents a channel member
@@ -98,13 +98,12 @@ impl Member {
     
     /// Get flag as string
     pub fn decoration(&self) -> String {
-        if self.has_privilege(OperatorPrivilege) {
-            "@".to_string()
-        } else if self.has_privilege(VoicePrivilege) {
-            "+".to_string()
-        } else {
-            "".to_string()
+        for &(privilege, prefix) in PREFIXES {
+            if self.has_privilege(privilege) {
+                return prefix.to_string()
+            }
         }
+        "".to_string()
     }
     
     /// Checks whether a member is the operator of the channel
@@ -113,9 +112,12 @@ impl Member {
     }
     
     /// Checks if any of members host mask matches any in the given set
+    ///
+    /// Both the real and the cloaked host mask are checked.
     pub fn mask_matches_any(&self, masks: &HashSet<HostMask>) -> bool {
+        let public_mask = self.client.info().public_hostmask().clone();
         for mask in masks.iter() {
-            if mask.matches(self.mask.as_str()) {
+            if mask.matches(self.mask.as_str()) || mask.matches(public_mask.as_str()) {
                 return true
             }
         }
@@ -139,7 +141,7 @@ impl Member {
         return &self.nick
     }
     
-    /// Getter for host
+    /// Getter for the real host
     pub fn hostname(&self) -> &str {
         return &self.hostname
     }
@@ -154,12 +156,37 @@ impl Member {
         return &self.realname
     }
     
+    /// Getter for the host shown to other users
+    pub fn public_hostname(&self) -> String {
+        self.client.info().public_host().to_string()
+    }
+    
+    /// Checks whether the member is marked as being away
+    pub fn is_away(&self) -> bool {
+        self.client.info().away().is_some()
+    }
+    
+    /// Checks whether the member is an IRC operator
+    pub fn is_operator(&self) -> bool {
+        self.client.info().is_operator()
+    }
+    
+    /// Checks whether the member is hidden from non-members
+    pub fn is_invisible(&self) -> bool {
+        self.client.info().has_mode(UserMode::Invisible)
+    }
+    
     /// Setter for nick
     pub fn set_nick(&mut self, nick: String) {
         self.nick = nick;
         self.update_decorated_nick()
     }
     
+    /// Setter for the host mask
+    pub fn set_mask(&mut self, mask: HostMask) {
+        self.mask = mask
+    }
+    
     /// Getter for client id
     pub fn id(&self) -> ClientId {
         self.id.clone()
@@ -88,6 +88,8 @@ pub enum ChannelMode {
     ReOpFlag = b'r' as isize,
     /// toggle the topic settable by channel operator only flag
     TopicProtect = b't' as isize,
+    /// toggle the secure clients only flag
+    SecureOnly = b'z' as isize,
     /// set/remove the channel key (password)
     ChannelKey = b'k' as isize,
     /// set/remove the user limit to channel
@@ -100,6 +102,32 @@ pub enum ChannelMode {
     InvitationMask = b'I' as isize
 }
 }
+/// Kind of a channel mode
+///
+/// Determines whether the mode takes a parameter.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum ModeKind {
+    /// Mode that manages a list of host masks
+    List,
+    /// Mode that always takes a parameter
+    Parameter,
+    /// Mode that takes a parameter only when it is set
+    SetParameter,
+    /// Mode that is a simple channel flag
+    Flag,
+    /// Mode that grants a privilege to a channel member
+    Privilege,
+}
+
+/// Nick prefixes of the member privileges, ordered by rank
+pub const PREFIXES: &'static [(ChannelMode, char)] = &[
+    (ChannelMode::OperatorPrivilege, '@'),
+    (ChannelMode::VoicePrivilege, '+'),
+];
+
+/// Maximum number of modes with a parameter per MODE message
+pub const MAX_MODES: usize = 6;
+
 /// Actions which determine what to do with a mode
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum Action {
@@ -112,17 +140,40 @@ pub enum Action {
 }
 
 impl ChannelMode {
-    fn has_parameter(&self) -> bool {
+    /// Returns all channel modes known to the server
+    pub fn all() -> Vec<ChannelMode> {
+        (0..128u8).filter_map(|v| FromPrimitive::from_u8(v)).collect()
+    }
+
+    /// Returns the kind of the mode
+    pub fn kind(&self) -> ModeKind {
     	use self::ChannelMode::*;
         match *self {
-            ChannelKey | UserLimit | BanMask
-            | ExceptionMask | InvitationMask
-            | OperatorPrivilege | VoicePrivilege => true,
-            _ => false
+            BanMask | ExceptionMask | InvitationMask => ModeKind::List,
+            ChannelKey => ModeKind::Parameter,
+            UserLimit => ModeKind::SetParameter,
+            ChannelCreator | OperatorPrivilege | VoicePrivilege => ModeKind::Privilege,
+            _ => ModeKind::Flag
+        }
+    }
+
+    fn has_parameter(&self, action: Action) -> bool {
+        match self.kind() {
+            ModeKind::List | ModeKind::Parameter | ModeKind::Privilege => action != Action::Show,
+            ModeKind::SetParameter => action == Action::Add,
+            ModeKind::Flag => false
         }
     }
 }
 
+/// Returns the channel modes of the given kind as a string
+pub fn modes_of_kind(kind: ModeKind) -> String {
+    ChannelMode::all().iter()
+        .filter(|mode| mode.kind() == kind)
+        .map(|&mode| mode as u8 as char)
+        .collect()
+}
+
 /// Parses the channel modes
 ///
 /// According to [RFC 2812] (http://tools.ietf.org/html/rfc2812#section-3.2.3) the
@@ -147,9 +198,12 @@ impl ChannelMode {
 /// ```
 /// 
 /// 
+///
+/// At most `MAX_MODES` modes with a parameter are processed.
 pub fn modes_do<Block>(mut params: Params, mut block: Block)
 where Block: FnMut(Action, ChannelMode, Option<&[u8]>) {
 	use self::Action::*;
+	let mut with_parameter = 0;
 	while let Some(current) = params.next() {
         // Bug: no +/- asking for modes
         let (action, offset) = match current[0] {
@@ -161,9 +215,12 @@ where Block: FnMut(Action, ChannelMode, Option<&[u8]>) {
         for mode in current[offset..].iter().filter_map( |&v| {
             let m: Option<ChannelMode> = FromPrimitive::from_u8(v); m
         }) {
-            let param = if mode.has_parameter() && action != Show {
-                let param = params.next();
-                param
+            let param = if mode.has_parameter(action) {
+                if with_parameter == MAX_MODES {
+                    return
+                }
+                with_parameter += 1;
+                params.next()
             } else {
                 None
             };
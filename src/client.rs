@@ -1,12 +1,14 @@
 //! High-level client communication
 use std::ops;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use mio;
 use std::io;
 use std::net;
 use std::mem;
 
 use rand;
+use time;
 
 use client_io;
 use user::User;
@@ -59,11 +61,95 @@ pub enum MessageOrigin {
     User
 }
 
+/// Traffic statistics of a connection
+///
+/// The counters are updated by the IO worker.
+#[derive(Debug)]
+pub struct Traffic {
+    sendq: AtomicUsize,
+    max_sendq: AtomicUsize,
+    sent_messages: AtomicUsize,
+    sent_bytes: AtomicUsize,
+    received_messages: AtomicUsize,
+    received_bytes: AtomicUsize,
+    connected: i64
+}
+
+impl Traffic {
+    fn new() -> Traffic {
+        Traffic {
+            sendq: AtomicUsize::new(0),
+            max_sendq: AtomicUsize::new(0),
+            sent_messages: AtomicUsize::new(0),
+            sent_bytes: AtomicUsize::new(0),
+            received_messages: AtomicUsize::new(0),
+            received_bytes: AtomicUsize::new(0),
+            connected: time::get_time().sec
+        }
+    }
+
+    /// Number of bytes waiting to be sent
+    pub fn sendq(&self) -> usize {
+        self.sendq.load(Ordering::Relaxed)
+    }
+    /// Maximum number of bytes waiting to be sent, given by the connection class
+    pub fn max_sendq(&self) -> usize {
+        self.max_sendq.load(Ordering::Relaxed)
+    }
+    /// Sets the send queue limit of the connection class
+    pub fn set_max_sendq(&self, bytes: usize) {
+        self.max_sendq.store(bytes, Ordering::Relaxed);
+    }
+    /// Number of messages sent to the client
+    pub fn sent_messages(&self) -> usize {
+        self.sent_messages.load(Ordering::Relaxed)
+    }
+    /// Number of bytes sent to the client
+    pub fn sent_bytes(&self) -> usize {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+    /// Number of messages received from the client
+    pub fn received_messages(&self) -> usize {
+        self.received_messages.load(Ordering::Relaxed)
+    }
+    /// Number of bytes received from the client
+    pub fn received_bytes(&self) -> usize {
+        self.received_bytes.load(Ordering::Relaxed)
+    }
+    /// Time stamp of the connection
+    pub fn connected(&self) -> i64 {
+        self.connected
+    }
+
+    /// Accounts a message that has been queued for sending
+    pub fn queued(&self, bytes: usize) {
+        self.sendq.fetch_add(bytes, Ordering::Relaxed);
+    }
+    /// Accounts bytes that have been written to the socket
+    pub fn sent(&self, bytes: usize, message_done: bool) {
+        self.sendq.fetch_sub(bytes, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+        if message_done {
+            self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    /// Accounts the queued bytes that were dropped
+    pub fn dropped(&self, bytes: usize) {
+        self.sendq.fetch_sub(bytes, Ordering::Relaxed);
+    }
+    /// Accounts a message that has been received
+    pub fn received(&self, bytes: usize) {
+        self.received_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Struct for client communication
 #[derive(Clone)]
 pub struct Client {
     id: ClientId,
     info: Arc<RwLock<User>>,
+    traffic: Arc<Traffic>,
     hostname: Arc<String>,
     channel: mio::Sender<client_io::Event>, 
 }
@@ -74,6 +160,7 @@ impl Client {
         Client {
             id: id,
             info: Arc::new(RwLock::new(user)),
+            traffic: Arc::new(Traffic::new()),
             hostname: hostname,
             channel: tx
         }
@@ -175,6 +262,11 @@ impl Client {
         self.info.write().unwrap()
     }
 
+    /// Getter for the traffic statistics
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
     /// Getter for id
     pub fn id(&self) -> ClientId {
         self.id
@@ -6,80 +6,464 @@ use std::convert::From;
 use std::io::Cursor;
 use std::io;
 use std::mem;
-use std::sync::Arc;
+use std::net::{SocketAddr, IpAddr};
+use std::sync::{Arc, Mutex, mpsc};
 use std::default::Default;
+use std::time::{Duration, Instant};
+use std::thread;
 
 use mio::{self, EventLoop, Handler, Token, TryRead, TryWrite, PollOpt, EventSet};
 use mio::tcp::TcpStream;
 use bytes::RingBuf;
+use openssl::ssl::{SslAcceptor, SslStream, MidHandshakeSslStream, HandshakeError};
 
 use protocol::{Message, Command};
 use protocol::ResponseCode::*;
 use client::{Client, ClientId, MessageOrigin};
 use user::{User, Status};
 use server;
+use net::{self, HostLookup};
+use ident;
+use sasl;
 
 /// Events that can be sent to `Worker`
 pub enum Event {
-    /// New TCP connection has been established
-    NewConnection(TcpStream),
+    /// New TCP connection has been established, the flag tells whether to use TLS,
+    /// the number is the send queue limit of the connection class
+    NewConnection(TcpStream, SocketAddr, bool, usize),
     /// Disconnect client
     Disconnect(ClientId),
     /// Raw message that should be send to the client as it is.
     Message(ClientId, Vec<u8>),
     /// Shared raw message that should be send to the client as it is.
     SharedMessage(ClientId, Arc<Vec<u8>>),
+    /// Result of the host name lookup of a new connection
+    HostLookup(ClientId, HostLookup),
     /// Shut down the event loop
     Shutdown
 }
 
+/// Timers that can be scheduled by `Worker`
+#[derive(Debug, Copy, Clone)]
+pub enum Timer {
+    /// Checks if the connection is still alive
+    Ping(Token),
+    /// Checks if the client registered in time
+    Registration(Token),
+    /// Gives up the host name and ident lookups
+    Lookup(Token),
+    /// Processes the messages delayed by the flood protection
+    Flood(Token),
+    /// Drops a closing connection that did not drain its send queue
+    Close(Token)
+}
+
+/// Settings for the client connections
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Seconds of inactivity after which the client is pinged
+    pub ping_frequency: u64,
+    /// Seconds to wait for any reply to a ping before the client is dropped
+    pub ping_timeout: u64,
+    /// Seconds a client may take to complete the registration
+    pub registration_timeout: u64,
+    /// Seconds to wait for the host name and ident lookups of a new connection
+    pub lookup_timeout: u64,
+    /// Port of the ident servers of the clients, `None` disables ident lookups
+    pub ident_port: Option<u16>,
+    /// Maximum length of a message including the trailing CR-LF
+    pub line_length: usize,
+    /// Maximum number of bytes waiting to be sent to a client without a connection class
+    pub sendq: usize,
+    /// Number of commands a client may send at once
+    pub flood_burst: u64,
+    /// Number of commands per second a client may send after the burst
+    pub flood_rate: u64,
+    /// Number of delayed commands after which a client is disconnected
+    pub flood_backlog: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            ping_frequency: 120,
+            ping_timeout: 60,
+            registration_timeout: 60,
+            lookup_timeout: 5,
+            ident_port: None,
+            line_length: 512,
+            sendq: 1048576,
+            flood_burst: 10,
+            flood_rate: 2,
+            flood_backlog: 64,
+        }
+    }
+}
+
+/// Keep-alive state of a connection
+struct Liveness {
+    last_activity: Instant,
+    ping_sent: Option<Instant>,
+    timer: Option<mio::Timeout>
+}
+
+impl Liveness {
+    fn new() -> Liveness {
+        Liveness {
+            last_activity: Instant::now(),
+            ping_sent: None,
+            timer: None
+        }
+    }
+}
+
+/// Maximum number of messages that are held back during the lookups
+const MAX_HELD_MESSAGES: usize = 32;
+
+/// Connection that waits for its host name and ident lookups
+///
+/// The server does not know about the client until the lookups finished,
+/// the messages of the client are held back until then.
+struct PendingLookup {
+    messages: Vec<Message>,
+    timer: Option<mio::Timeout>,
+    host: bool,
+    ident: bool
+}
+
+impl PendingLookup {
+    /// Holds back a message, excess messages are dropped
+    fn hold(&mut self, msg: Message) {
+        if self.messages.len() < MAX_HELD_MESSAGES {
+            self.messages.push(msg)
+        }
+    }
+
+    /// Checks whether all lookups finished
+    fn done(&self) -> bool {
+        !self.host && !self.ident
+    }
+}
+
+/// Number of threads that look up host names
+const RESOLVER_THREADS: usize = 4;
+
+/// Maximum number of host name lookups waiting for a resolver thread
+const MAX_QUEUED_LOOKUPS: usize = 256;
+
+/// Host name lookup waiting for a resolver thread
+struct HostRequest {
+    id: ClientId,
+    addr: IpAddr,
+    deadline: Instant,
+    tx: mio::Sender<Event>
+}
+
+/// Token of the ident query of a connection
+fn ident_token(token: Token) -> Token {
+    Token(!token.as_usize())
+}
+
+/// Bounded pool of threads that look up host names
+///
+/// Requests that are past their deadline when a thread picks them up
+/// are dropped, the lookup timer of the connection gave up on them.
+struct Resolver {
+    tx: mpsc::SyncSender<HostRequest>
+}
+
+impl Resolver {
+    fn new() -> Resolver {
+        let (tx, rx) = mpsc::sync_channel::<HostRequest>(MAX_QUEUED_LOOKUPS);
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..RESOLVER_THREADS {
+            let rx = rx.clone();
+            thread::spawn(move || loop {
+                let request = match rx.lock().map(|rx| rx.recv()) {
+                    Ok(Ok(request)) => request,
+                    _ => break // worker is gone
+                };
+                if Instant::now() < request.deadline {
+                    let result = net::lookup_host_name(request.addr);
+                    let _ = request.tx.send(Event::HostLookup(request.id, result));
+                }
+            });
+        }
+        Resolver {
+            tx: tx
+        }
+    }
+
+    /// Queues a lookup, returns `false` if the queue is full
+    fn lookup(&self, request: HostRequest) -> bool {
+        self.tx.try_send(request).is_ok()
+    }
+}
+
+/// Token bucket limiting the rate of the commands of a client
+///
+/// Every command costs a penalty, see `penalty`. A command is processed
+/// if at least one token is left, otherwise it is delayed until the bucket
+/// has been refilled.
+struct FloodControl {
+    tokens: f64,
+    updated: Instant,
+    backlog: VecDeque<(f64, Message)>,
+    timer: Option<mio::Timeout>,
+    /// The client is being disconnected for flooding
+    excess: bool
+}
+
+impl FloodControl {
+    fn new(settings: &Settings) -> FloodControl {
+        FloodControl {
+            tokens: settings.flood_burst as f64,
+            updated: Instant::now(),
+            backlog: VecDeque::new(),
+            timer: None,
+            excess: false
+        }
+    }
+
+    /// Refills the bucket for the time passed since the last update
+    fn refill(&mut self, settings: &Settings) {
+        let now = Instant::now();
+        let passed = as_millis(now.duration_since(self.updated)) as f64 / 1000.;
+        self.tokens = (self.tokens + passed * settings.flood_rate as f64).min(settings.flood_burst as f64);
+        self.updated = now;
+    }
+
+    /// Takes the penalty of a command if it can be processed right away
+    ///
+    /// Commands are never reordered, thus nothing is processed while
+    /// other commands are delayed.
+    fn admit(&mut self, cost: f64, settings: &Settings) -> bool {
+        self.refill(settings);
+        if self.backlog.is_empty() && self.tokens >= 1. {
+            self.tokens -= cost;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Milliseconds until the next command can be processed
+    fn delay(&self, settings: &Settings) -> u64 {
+        ((1. - self.tokens).max(0.) * 1000. / settings.flood_rate as f64).ceil() as u64
+    }
+
+    /// Passes a command of the client through the flood protection
+    ///
+    /// Returns the command if it can be processed right away, `None` if it
+    /// has been delayed or dropped and `Err` if the backlog is full.
+    fn check(&mut self, msg: Message, exempt: bool, token: Token, event_loop: &mut EventLoop<Worker>,
+             settings: &Settings) -> Result<Option<Message>, ()> {
+        if self.excess {
+            return Ok(None)
+        }
+        let cost = penalty(&msg);
+        if exempt || self.admit(cost, settings) {
+            return Ok(Some(msg))
+        }
+        if self.backlog.len() >= settings.flood_backlog {
+            return Err(())
+        }
+        self.backlog.push_back((cost, msg));
+        if self.timer.is_none() {
+            match event_loop.timeout_ms(Timer::Flood(token), self.delay(settings)) {
+                Ok(timer) => self.timer = Some(timer),
+                Err(err) => error!("failed to schedule flood timer: {:?}", err)
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Penalty of a command for the flood protection
+///
+/// Joining or parting several channels costs one token per channel,
+/// commands with large replies cost more than one token.
+fn penalty(msg: &Message) -> f64 {
+    use protocol::Command::*;
+    let channels = || msg.params().next().map_or(1, |channels| {
+        channels.split(|&c| c == b',').count()
+    }) as f64;
+    match msg.command() {
+        Some(PONG) => 0.,
+        Some(JOIN) | Some(PART) => channels(),
+        Some(NICK) | Some(WHO) | Some(WHOIS) | Some(LIST) | Some(NAMES) => 2.,
+        _ => 1.
+    }
+}
+
+/// Converts a duration to milliseconds
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Milliseconds a closing connection may take to drain its send queue
+const CLOSE_TIMEOUT: u64 = 5000;
+
+/// Marks the client as connected via TLS and records its certificate
+fn tls_established(client: &Client, stream: &SslStream<TcpStream>) {
+    let certfp = stream.ssl().peer_certificate().and_then(|cert| sasl::fingerprint(&cert));
+    let mut info = client.info_mut();
+    info.set_secure(true);
+    info.set_certfp(certfp)
+}
+
+/// Transport of a client connection
+enum Stream {
+    /// Plain text connection
+    Plain(TcpStream),
+    /// TLS connection that did not finish the handshake yet
+    Handshake(MidHandshakeSslStream<TcpStream>),
+    /// Established TLS connection
+    Tls(SslStream<TcpStream>)
+}
+
+impl Stream {
+    /// Getter for the underlying socket
+    fn socket(&self) -> &TcpStream {
+        match *self {
+            Stream::Plain(ref stream) => stream,
+            Stream::Handshake(ref stream) => stream.get_ref(),
+            Stream::Tls(ref stream) => stream.get_ref()
+        }
+    }
+
+    /// Number of decrypted bytes that can be read without touching the socket
+    fn pending(&self) -> usize {
+        match *self {
+            Stream::Tls(ref stream) => stream.ssl().pending(),
+            _ => 0
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.read(buf),
+            Stream::Handshake(_) => Err(io::Error::new(io::ErrorKind::WouldBlock, "TLS handshake in progress")),
+            Stream::Tls(ref mut stream) => stream.read(buf)
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.write(buf),
+            Stream::Handshake(_) => Err(io::Error::new(io::ErrorKind::WouldBlock, "TLS handshake in progress")),
+            Stream::Tls(ref mut stream) => stream.write(buf)
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Plain(ref mut stream) => stream.flush(),
+            Stream::Handshake(_) => Ok(()),
+            Stream::Tls(ref mut stream) => stream.flush()
+        }
+    }
+}
+
 /// Event handler for client communication
 pub struct Worker {
-    streams: HashMap<Token, TcpStream>,
+    streams: HashMap<Token, Stream>,
     clients: HashMap<Token, Client>,
     readers: HashMap<Token, MessageReader>,
-    buffers: HashMap<Token, VecDeque<Cursor<Vec<u8>>>>,
+    buffers: HashMap<Token, VecDeque<Cursor<Arc<Vec<u8>>>>>,
+    liveness: HashMap<Token, Liveness>,
+    floods: HashMap<Token, FloodControl>,
+    deadlines: HashMap<Token, mio::Timeout>,
+    lookups: HashMap<Token, PendingLookup>,
+    idents: HashMap<Token, ident::Query>,
+    closing: HashMap<Token, mio::Timeout>,
+    resolver: Resolver,
     server_tx: mio::Sender<server::Event>,
-    host: Arc<String>
-
+    host: Arc<String>,
+    settings: Settings,
+    tls: Option<SslAcceptor>
 }
 
 impl Worker {
     /// Constructs a new worker
-    pub fn new(tx: mio::Sender<server::Event>, host: Arc<String>) -> Worker {
+    pub fn new(tx: mio::Sender<server::Event>, host: Arc<String>, settings: Settings,
+               tls: Option<SslAcceptor>) -> Worker {
         Worker {
             streams: HashMap::new(),
             clients: HashMap::new(),
             readers: HashMap::new(),
             buffers: HashMap::new(),
+            liveness: HashMap::new(),
+            floods: HashMap::new(),
+            deadlines: HashMap::new(),
+            lookups: HashMap::new(),
+            idents: HashMap::new(),
+            closing: HashMap::new(),
+            resolver: Resolver::new(),
             server_tx: tx,
-            host: host
+            host: host,
+            settings: settings,
+            tls: tls
         }
     }
 
     /// Registers a new connection
-    fn register_connection(&mut self, mut stream: TcpStream, 
-                           event_loop: &mut EventLoop<Worker>) -> io::Result<ClientId>
+    fn register_connection(&mut self, mut stream: TcpStream, peer: SocketAddr, secure: bool,
+                           sendq: usize, event_loop: &mut EventLoop<Worker>) -> io::Result<ClientId>
     {
         let id = try!(ClientId::new(&stream));
-        let client_hostname = ::net::get_nameinfo(try!(stream.peer_addr()));
+        let local = try!(stream.local_addr());
+        let mut user = User::new(net::host_from_ip(peer.ip()));
+        user.set_ip(peer.ip());
+        let registration_timeout = self.settings.registration_timeout;
+        user.set_registration_deadline(Instant::now() + Duration::from_secs(registration_timeout));
         let client = Client::new(
             id,
-            User::new(client_hostname),
+            user,
             event_loop.channel(),
             self.host.clone(),
         );
+        client.traffic().set_max_sendq(sendq);
         let token = id.token();
         if let Ok(()) = event_loop.register(
                 &mut stream, token, 
                 EventSet::readable() | EventSet::writable() | EventSet::hup(), 
                 PollOpt::edge()
         ) {
+            let stream = if secure {
+                match self.tls.as_ref().map(|acceptor| acceptor.accept(stream)) {
+                    Some(Ok(stream)) => {
+                        tls_established(&client, &stream);
+                        Stream::Tls(stream)
+                    },
+                    Some(Err(HandshakeError::WouldBlock(stream))) => Stream::Handshake(stream),
+                    Some(Err(HandshakeError::Failure(stream))) => {
+                        let _ = event_loop.deregister(stream.get_ref());
+                        return Err(io::Error::new(io::ErrorKind::Other, "TLS handshake failed."))
+                    },
+                    _ => return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Failed to set up TLS."
+                    ))
+                }
+            } else {
+                Stream::Plain(stream)
+            };
             self.streams.insert(token, stream);
             self.clients.insert(token, client.clone());
-            self.readers.insert(token, Default::default());
+            self.readers.insert(token, MessageReader::new(self.settings.line_length));
             self.buffers.insert(token, VecDeque::new());
-            let _ = self.server_tx.send(server::Event::Connected(client));
+            self.liveness.insert(token, Liveness::new());
+            self.floods.insert(token, FloodControl::new(&self.settings));
+            self.schedule_ping(event_loop, token, self.settings.ping_frequency * 1000);
+            match event_loop.timeout_ms(Timer::Registration(token), registration_timeout * 1000) {
+                Ok(timer) => { self.deadlines.insert(token, timer); },
+                Err(err) => error!("failed to schedule registration timer: {:?}", err)
+            }
+            self.start_lookups(event_loop, &client, local, peer);
             Ok(id)
         } else {
             Err(io::Error::new(
@@ -89,82 +473,563 @@ impl Worker {
         }
     }
 
+    /// Looks up the host name and the ident of a new connection in the background
+    ///
+    /// The client is announced to the server once the lookups finished
+    /// or timed out.
+    fn start_lookups(&mut self, event_loop: &mut EventLoop<Worker>, client: &Client,
+                     local: SocketAddr, peer: SocketAddr) {
+        let token = client.id().token();
+        let timeout = self.settings.lookup_timeout;
+        let timer = match event_loop.timeout_ms(Timer::Lookup(token), timeout * 1000) {
+            Ok(timer) => Some(timer),
+            Err(err) => {
+                error!("failed to schedule lookup timer: {:?}", err);
+                None
+            }
+        };
+        self.lookups.insert(token, PendingLookup {
+            messages: Vec::new(),
+            timer: timer,
+            host: true,
+            ident: self.settings.ident_port.is_some()
+        });
+        let id = client.id();
+        self.send_notice(event_loop, token, "*** Looking up your hostname...");
+        let queued = self.resolver.lookup(HostRequest {
+            id: id,
+            addr: peer.ip(),
+            deadline: Instant::now() + Duration::from_secs(timeout),
+            tx: event_loop.channel()
+        });
+        if !queued {
+            error!("too many host name lookups, skipping the one of {}", peer.ip());
+            self.host_lookup_done(event_loop, token, Some(HostLookup::NotFound))
+        }
+        if let Some(port) = self.settings.ident_port {
+            self.send_notice(event_loop, token, "*** Checking Ident");
+            let query = ident::Query::start(local, peer, port).and_then(|query| event_loop.register(
+                query.stream(), ident_token(token),
+                EventSet::readable() | EventSet::writable() | EventSet::hup(),
+                PollOpt::edge()
+            ).map(|_| query));
+            match query {
+                Ok(query) => { self.idents.insert(token, query); },
+                Err(err) => {
+                    debug!("ident query for {} failed: {}", peer, err);
+                    self.ident_lookup_done(event_loop, token, None)
+                }
+            }
+        }
+    }
+
+    /// Applies the result of the host name lookup
+    ///
+    /// `None` means that the lookup timed out.
+    fn host_lookup_done(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, result: Option<HostLookup>) {
+        if !self.lookups.get_mut(&token).map_or(false, |pending| mem::replace(&mut pending.host, false)) {
+            return // timed out or connection already closed
+        }
+        match result {
+            Some(HostLookup::Found(host)) => {
+                if let Some(client) = self.clients.get(&token) {
+                    client.info_mut().set_host(host);
+                }
+                self.send_notice(event_loop, token, "*** Found your hostname")
+            },
+            Some(HostLookup::Mismatch) => self.send_notice(
+                event_loop, token, "*** Your forward and reverse DNS do not match, ignoring hostname"
+            ),
+            Some(HostLookup::NotFound) => self.send_notice(
+                event_loop, token, "*** Couldn't look up your hostname"
+            ),
+            None => self.send_notice(
+                event_loop, token, "*** Couldn't look up your hostname (timed out)"
+            )
+        }
+        self.finish_lookups(event_loop, token)
+    }
+
+    /// Applies the result of the ident lookup
+    fn ident_lookup_done(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, ident: Option<String>) {
+        if !self.lookups.get_mut(&token).map_or(false, |pending| mem::replace(&mut pending.ident, false)) {
+            return // timed out or not enabled or connection already closed
+        }
+        if let Some(query) = self.idents.remove(&token) {
+            let _ = event_loop.deregister(query.stream());
+        }
+        if ident.is_some() {
+            self.send_notice(event_loop, token, "*** Got Ident response")
+        } else {
+            self.send_notice(event_loop, token, "*** No Ident response")
+        }
+        if let Some(client) = self.clients.get(&token) {
+            client.info_mut().set_ident(ident)
+        }
+        self.finish_lookups(event_loop, token)
+    }
+
+    /// Continues the ident query of a connection
+    fn ident_ready(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, events: EventSet) {
+        let result = match self.idents.get_mut(&token) {
+            Some(query) => query.ready(events),
+            None => return
+        };
+        if let Some(ident) = result {
+            self.ident_lookup_done(event_loop, token, ident)
+        }
+    }
+
+    /// Gives up the lookups that did not finish in time
+    fn lookups_expired(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        if let Some(pending) = self.lookups.get_mut(&token) {
+            pending.timer = None
+        }
+        self.host_lookup_done(event_loop, token, None);
+        self.ident_lookup_done(event_loop, token, None)
+    }
+
+    /// Hands the client to the server once all lookups finished
+    fn finish_lookups(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        if !self.lookups.get(&token).map_or(false, |pending| pending.done()) {
+            return
+        }
+        let pending = self.lookups.remove(&token).unwrap();
+        if let Some(timer) = pending.timer {
+            let _ = event_loop.clear_timeout(timer);
+        }
+        let client = match self.clients.get(&token) {
+            Some(client) => client.clone(),
+            None => return
+        };
+        let _ = self.server_tx.send(server::Event::Connected(client.clone()));
+        let exempt = client.info().is_operator();
+        for msg in pending.messages {
+            let msg = match self.floods.get_mut(&token) {
+                Some(flood) => match flood.check(msg, exempt, token, event_loop, &self.settings) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => continue,
+                    Err(()) => return self.excess_flood(event_loop, token)
+                },
+                None => msg
+            };
+            let _ = self.server_tx.send(server::Event::InboundMessage(client.id(), msg));
+        }
+    }
+
+    /// Sends a notice to a client that did not register yet
+    fn send_notice(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, text: &str) {
+        let msg = match self.clients.get(&token) {
+            Some(client) => client.build_msg(Command::NOTICE, &["*", text], MessageOrigin::Server),
+            None => return
+        };
+        self.queue(event_loop, token, Arc::new(msg))
+    }
+
+    /// Passes a message of the client to the server
+    ///
+    /// The message is held back if the host name lookup did not finish yet.
+    /// Returns `false` if the server thread is gone.
+    fn forward(&mut self, token: Token, msg: Message) -> bool {
+        if let Some(pending) = self.lookups.get_mut(&token) {
+            pending.hold(msg);
+            return true
+        }
+        match self.clients.get(&token) {
+            Some(client) => self.server_tx.send(server::Event::InboundMessage(client.id(), msg)).is_ok(),
+            None => true
+        }
+    }
+
+    /// Removes the connection and all of its state
+    ///
+    /// The stream may be gone already if the TLS setup failed.
     fn unregister_connection(&mut self, token: &Token, event_loop: &mut EventLoop<Worker>) {
         if let Some(stream) = self.streams.remove(token) {
-            let _ = event_loop.deregister(&stream);
-        } else {
-            return // connection already closed
+            let _ = event_loop.deregister(stream.socket());
+        }
+        let client = match self.clients.remove(token) {
+            Some(client) => client,
+            None => return // connection already closed
+        };
+        if let Some(ip) = client.info().ip() {
+            let _ = self.server_tx.send(server::Event::ConnectionClosed(ip));
+        }
+        if let Some(query) = self.idents.remove(token) {
+            let _ = event_loop.deregister(query.stream());
+        }
+        match self.lookups.remove(token) {
+            // The server does not know the client yet
+            Some(pending) => if let Some(timer) = pending.timer {
+                let _ = event_loop.clear_timeout(timer);
+            },
+            None => {
+                let _ = self.server_tx.send(server::Event::Disconnected(client));
+            }
         }
-        let _ = self.server_tx.send(server::Event::Disconnected(self.clients[token].clone()));
         self.readers.remove(token);
-        self.clients.remove(token);
         self.buffers.remove(token);
+        if let Some(timer) = self.floods.remove(token).and_then(|v| v.timer) {
+            let _ = event_loop.clear_timeout(timer);
+        }
+        if let Some(timer) = self.liveness.remove(token).and_then(|v| v.timer) {
+            let _ = event_loop.clear_timeout(timer);
+        }
+        if let Some(timer) = self.deadlines.remove(token) {
+            let _ = event_loop.clear_timeout(timer);
+        }
+        if let Some(timer) = self.closing.remove(token) {
+            let _ = event_loop.clear_timeout(timer);
+        }
+    }
+
+    /// Closes the connection once the send queue has been drained
+    ///
+    /// Nothing is read from a closing connection anymore. It is dropped
+    /// after `CLOSE_TIMEOUT` if the client does not accept the data.
+    fn close(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        if self.closing.contains_key(&token) {
+            return
+        }
+        if self.buffers.get(&token).map_or(true, |buffers| buffers.is_empty()) {
+            self.unregister_connection(&token, event_loop)
+        } else {
+            match event_loop.timeout_ms(Timer::Close(token), CLOSE_TIMEOUT) {
+                Ok(timer) => { self.closing.insert(token, timer); },
+                Err(err) => {
+                    error!("failed to schedule close timer: {:?}", err);
+                    self.unregister_connection(&token, event_loop)
+                }
+            }
+        }
+    }
+
+    /// Drops a closing connection that did not drain its send queue in time
+    fn close_expired(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        if self.closing.remove(&token).is_some() {
+            self.unregister_connection(&token, event_loop)
+        }
+    }
+
+    /// Sends an `ERROR` message to the client and closes the connection
+    fn close_link(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, reason: &str) {
+        let msg = format!("ERROR :Closing Link ({})\r\n", reason).into_bytes();
+        self.queue(event_loop, token, Arc::new(msg));
+        self.close(event_loop, token)
+    }
+
+    /// Closes the connection if the client did not register in time
+    fn check_registration(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        let _ = self.deadlines.remove(&token);
+        let expired = match self.clients.get(&token) {
+            Some(client) => client.info().registration_expired(Instant::now()),
+            None => return // connection already closed
+        };
+        if expired {
+            self.close_link(event_loop, token, "Registration timeout")
+        }
+    }
+
+    /// Schedules the next liveness check of the connection
+    fn schedule_ping(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, delay: u64) {
+        if let Some(liveness) = self.liveness.get_mut(&token) {
+            match event_loop.timeout_ms(Timer::Ping(token), delay) {
+                Ok(timer) => liveness.timer = Some(timer),
+                Err(err) => error!("failed to schedule ping timer: {:?}", err)
+            }
+        }
+    }
+
+    /// Checks if the connection is still alive
+    ///
+    /// Pings the client if it has been idle for too long and
+    /// disconnects it if it did not respond in time.
+    fn check_alive(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        let frequency = self.settings.ping_frequency * 1000;
+        let timeout = self.settings.ping_timeout * 1000;
+        let now = Instant::now();
+        let (idle, ping_sent) = match self.liveness.get(&token) {
+            Some(liveness) => (
+                as_millis(now.duration_since(liveness.last_activity)),
+                liveness.ping_sent.map(|sent| as_millis(now.duration_since(sent)))
+            ),
+            None => return // connection already closed
+        };
+        match ping_sent {
+            Some(waiting) if waiting >= timeout => {
+                let msg = match self.clients.get(&token) {
+                    Some(client) => Message::new(client.build_msg(
+                        Command::QUIT, &[&*format!("Ping timeout: {} seconds", idle / 1000)], MessageOrigin::User
+                    )).unwrap(),
+                    None => return
+                };
+                // The quit message will trigger a disconnect event
+                let _ = self.forward(token, msg);
+            },
+            Some(waiting) => self.schedule_ping(event_loop, token, timeout - waiting),
+            None if idle >= frequency => {
+                let msg = match self.clients.get(&token) {
+                    Some(client) => client.build_msg(Command::PING, &[&*self.host], MessageOrigin::Server),
+                    None => return
+                };
+                self.liveness.get_mut(&token).unwrap().ping_sent = Some(now);
+                self.queue(event_loop, token, Arc::new(msg));
+                self.schedule_ping(event_loop, token, timeout)
+            },
+            None => self.schedule_ping(event_loop, token, frequency - idle)
+        }
+    }
+
+    /// Continues the TLS handshake
+    ///
+    /// Returns `true` if the connection is ready for application data.
+    fn handshake(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) -> bool {
+        let stream = match self.streams.remove(&token) {
+            Some(Stream::Handshake(stream)) => stream,
+            Some(stream) => {
+                self.streams.insert(token, stream);
+                return true
+            },
+            None => return false
+        };
+        match stream.handshake() {
+            Ok(stream) => {
+                if let Some(client) = self.clients.get(&token) {
+                    tls_established(client, &stream);
+                }
+                self.streams.insert(token, Stream::Tls(stream));
+                true
+            },
+            Err(HandshakeError::WouldBlock(stream)) => {
+                self.streams.insert(token, Stream::Handshake(stream));
+                false
+            },
+            Err(HandshakeError::Failure(stream)) => {
+                debug!("TLS handshake failed: {:?}", stream.error());
+                self.streams.insert(token, Stream::Handshake(stream));
+                self.unregister_connection(&token, event_loop);
+                false
+            },
+            Err(HandshakeError::SetupFailure(err)) => {
+                error!("TLS setup failed: {}", err);
+                // The socket was closed together with the stream which
+                // also removed it from the event loop
+                self.unregister_connection(&token, event_loop);
+                false
+            }
+        }
+    }
+
+    /// Queues a message for sending
+    ///
+    /// Shared messages are queued without copying them. The client is
+    /// disconnected if its send queue grows beyond the limit.
+    fn queue(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, msg: Arc<Vec<u8>>) {
+        let exceeded = match (self.buffers.get_mut(&token), self.clients.get(&token)) {
+            (Some(buffers), Some(client)) => {
+                let traffic = client.traffic();
+                if traffic.sendq() + msg.len() > traffic.max_sendq() {
+                    true
+                } else {
+                    traffic.queued(msg.len());
+                    buffers.push_back(Cursor::new(msg));
+                    false
+                }
+            },
+            _ => return
+        };
+        if exceeded {
+            self.sendq_exceeded(token)
+        } else {
+            self.writable(event_loop, token)
+        }
+    }
+
+    /// Drops the send queue of a client that does not keep up and disconnects it
+    fn sendq_exceeded(&mut self, token: Token) {
+        let buffers = self.buffers.remove(&token);
+        let msg = match self.clients.get(&token) {
+            Some(client) => {
+                debug!("send queue of {} exceeded", client.info().real_hostmask().as_str());
+                for buffer in buffers.iter().flat_map(|buffers| buffers.iter()) {
+                    client.traffic().dropped(buffer.get_ref().len() - buffer.position() as usize)
+                }
+                Message::new(client.build_msg(
+                    Command::QUIT, &["Max SendQ exceeded"], MessageOrigin::User
+                )).unwrap()
+            },
+            None => return
+        };
+        // The quit message will trigger a disconnect event,
+        // further messages are dropped until then
+        let _ = self.forward(token, msg);
     }
     
-    fn readable(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, events: mio::EventSet) {
+    /// Reads from the socket and passes the messages to the server
+    ///
+    /// Returns `true` if data was read and the socket might not be drained yet.
+    fn readable(&mut self, event_loop: &mut EventLoop<Worker>, token: Token, events: mio::EventSet) -> bool {
         use protocol::Command::*;
         if events.is_error() || events.is_hup() {
-            if let Some(client) = self.clients.get(&token) {
-                // The quit message will trigger a disconnect event
-                let _ = self.server_tx.send(server::Event::InboundMessage(client.id(), Message::new(client.build_msg(
+            let msg = match self.clients.get(&token) {
+                Some(client) => Message::new(client.build_msg(
                     QUIT, &["Client hung up"], MessageOrigin::User
-                )).unwrap()));
-            }
+                )).unwrap(),
+                None => return false
+            };
+            // The quit message will trigger a disconnect event
+            let _ = self.forward(token, msg);
+            false
         } else {
             if let Some(stream) = self.streams.get_mut(&token) {
                 let reader = &mut self.readers.get_mut(&token).unwrap();
                 let client = &self.clients[&token];
-                match reader.feed(stream) {
-                    Ok(messages) => for message in messages {
-                        match message.map(|m| Message::new(m)) {
-                            Ok(Ok(msg)) => {
-                                debug!("received message {:?}", String::from_utf8_lossy(&*msg));
-                                if let Some(cmd) = msg.command() {
-                                    if client.info().status() != Status::Registered {
-                                        match cmd {
-                                            CAP | NICK | USER | QUIT => (),
-                                            cmd => {
-                                                // User is not registered, ignore other messages for now
-                                                debug!("User not yet registered ignored {} message.", cmd);
-                                                continue
+                let mut excess_flood = false;
+                let read = match reader.feed(stream) {
+                    Ok(0) => false,
+                    Ok(_) => {
+                        for message in reader.by_ref() {
+                            if let Some(liveness) = self.liveness.get_mut(&token) {
+                                liveness.last_activity = Instant::now();
+                                liveness.ping_sent = None;
+                            }
+                            if let Ok(ref message) = message {
+                                // Including the CR-LF
+                                client.traffic().received(message.len() + 2)
+                            }
+                            match message.map(|m| Message::new(m)) {
+                                Ok(Ok(msg)) => {
+                                    debug!("received message {:?}", String::from_utf8_lossy(&*msg));
+                                    if let Some(cmd) = msg.command() {
+                                        if client.info().status() != Status::Registered {
+                                            match cmd {
+                                                CAP | AUTHENTICATE | NICK | USER | QUIT | PING | PONG => (),
+                                                cmd => {
+                                                    // User is not registered, ignore other messages for now
+                                                    debug!("User not yet registered ignored {} message.", cmd);
+                                                    continue
+                                                }
                                             }
                                         }
+                                        if let Some(pending) = self.lookups.get_mut(&token) {
+                                            pending.hold(msg);
+                                            continue
+                                        }
+                                        // Operators are exempt
+                                        let exempt = client.info().is_operator();
+                                        let msg = match self.floods.get_mut(&token) {
+                                            Some(flood) => match flood.check(msg, exempt, token, event_loop, &self.settings) {
+                                                Ok(Some(msg)) => msg,
+                                                Ok(None) => continue,
+                                                Err(()) => {
+                                                    excess_flood = true;
+                                                    break
+                                                }
+                                            },
+                                            None => msg
+                                        };
+                                        if let Err(_) = self.server_tx.send(server::Event::InboundMessage(client.id(), msg)) {
+                                            // Server thread crashed, quitting client thread
+                                            event_loop.shutdown()
+                                        }
+                                    } else {
+                                        client.send_response(
+                                            ERR_UNKNOWNCOMMAND, 
+                                            &[&*String::from_utf8_lossy(msg.command_bytes()), "Unknown command"]
+                                        )
                                     }
-                                    if let Err(_) = self.server_tx.send(server::Event::InboundMessage(client.id(), msg)) {
-                                        // Server thread crashed, quitting client thread
-                                        event_loop.shutdown()
-                                    }
-                                } else {
-                                    client.send_response(
-                                        ERR_UNKNOWNCOMMAND, 
-                                        &[&*String::from_utf8_lossy(msg.command_bytes()), "Unknown command"]
-                                    )
-                                }
-                            },
-                            Ok(Err(err)) => debug!("{:?}", err),
-                            Err(err) => debug!("{:?}", err)
+                                },
+                                Ok(Err(err)) => debug!("{:?}", err),
+                                Err(err) => debug!("{:?}", err)
+                            }
                         }
+                        true
                     },
-                    Err(err) => debug!("{:?}", err)
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => false,
+                    Err(err) => {
+                        debug!("{:?}", err);
+                        false
+                    }
+                };
+                if !excess_flood {
+                    return read
                 }
+            } else {
+                return false
             }
+            self.excess_flood(event_loop, token);
+            false
         }
     }
+
+    /// Processes the delayed commands the client has enough tokens for
+    fn release_delayed(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        let mut released = Vec::new();
+        if let Some(flood) = self.floods.get_mut(&token) {
+            flood.timer = None;
+            flood.refill(&self.settings);
+            while flood.tokens >= 1. {
+                match flood.backlog.pop_front() {
+                    Some((cost, msg)) => {
+                        flood.tokens -= cost;
+                        released.push(msg)
+                    },
+                    None => break
+                }
+            }
+            if !flood.backlog.is_empty() {
+                let delay = flood.delay(&self.settings);
+                match event_loop.timeout_ms(Timer::Flood(token), delay) {
+                    Ok(timer) => flood.timer = Some(timer),
+                    Err(err) => error!("failed to schedule flood timer: {:?}", err)
+                }
+            }
+        }
+        for msg in released {
+            if !self.forward(token, msg) {
+                // Server thread crashed, quitting client thread
+                event_loop.shutdown()
+            }
+        }
+    }
+
+    /// Disconnects a client that sent too many commands
+    fn excess_flood(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        let msg = match self.clients.get(&token) {
+            Some(client) => {
+                debug!("excess flood from {}", client.info().real_hostmask().as_str());
+                Message::new(client.build_msg(
+                    Command::QUIT, &["Excess Flood"], MessageOrigin::User
+                )).unwrap()
+            },
+            None => return
+        };
+        // Further commands are dropped, the quit message will trigger a disconnect event
+        if let Some(flood) = self.floods.get_mut(&token) {
+            flood.backlog.clear();
+            flood.excess = true
+        }
+        self.queue(event_loop, token, Arc::new(b"ERROR :Closing Link (Excess Flood)\r\n".to_vec()));
+        let _ = self.forward(token, msg);
+    }
     
-    fn writable(&mut self, _: &mut EventLoop<Worker>, token: Token) {
-        if let Some(stream) = self.streams.get_mut(&token) {
-            let buffers = &mut self.buffers.get_mut(&token).unwrap();
+    fn writable(&mut self, event_loop: &mut EventLoop<Worker>, token: Token) {
+        let mut drained = false;
+        if let (Some(stream), Some(buffers), Some(client)) = (
+            self.streams.get_mut(&token), self.buffers.get_mut(&token), self.clients.get(&token)
+        ) {
             while buffers.len() > 0 {
                 let mut drop_front = false;
                 {
                     let buffer = &mut buffers[0];
                     let max_pos = buffer.get_ref().len() as u64;
-                    match stream.write(&*buffer.get_ref()) {
+                    let pos = buffer.position();
+                    match stream.write(&buffer.get_ref()[pos as usize..]) {
                         Ok(bytes) => {
-                            let new_pos = buffer.position() + bytes as u64;
-                            if new_pos == max_pos {
-                                drop_front = true;
-                            } else {
+                            let new_pos = pos + bytes as u64;
+                            drop_front = new_pos == max_pos;
+                            client.traffic().sent(bytes, drop_front);
+                            if !drop_front {
                                 buffer.set_position(new_pos)
                             }
                         },
@@ -175,52 +1040,82 @@ impl Worker {
                     let _ = buffers.remove(0);
                 }
             }
+            drained = buffers.is_empty();
+        }
+        if drained && self.closing.contains_key(&token) {
+            self.unregister_connection(&token, event_loop)
         }
     }
 }
 
 impl Handler for Worker {
-    type Timeout = ();
+    type Timeout = Timer;
     type Message = Event;
 
     fn notify(&mut self, event_loop: &mut EventLoop<Worker>, msg: Event) {
         use self::Event::*;
         match msg {
-            NewConnection(stream) => {
+            NewConnection(stream, addr, secure, sendq) => {
                 // If it didn’t work the client closed the connection, never mind.
-                let _ = self.register_connection(stream, event_loop);
+                if let Err(_) = self.register_connection(stream, addr, secure, sendq, event_loop) {
+                    let _ = self.server_tx.send(server::Event::ConnectionClosed(addr.ip()));
+                }
             },
             Disconnect(id) => {
-                self.unregister_connection(&id.token(), event_loop);
+                self.close(event_loop, id.token());
             },
             Shutdown => {
                 event_loop.shutdown()
             },
+            HostLookup(id, result) => {
+                self.host_lookup_done(event_loop, id.token(), Some(result))
+            },
             Message(id, vec) => {
                 debug!(" sending message {}", String::from_utf8_lossy(&vec));
-                let token = id.token();
-                if self.buffers.contains_key(&token) {
-                    self.buffers.get_mut(&token).unwrap().push_back(Cursor::new(vec));
-                    self.writable(event_loop, token)
-                }
+                self.queue(event_loop, id.token(), Arc::new(vec))
             },
             SharedMessage(id, vec) => {
                 debug!(" sending message {}", String::from_utf8_lossy(&vec));
-                // TODO do not clone, Cursor should also work for soon
-                let token = id.token();
-                if self.buffers.contains_key(&token) {
-                    self.buffers.get_mut(&token).unwrap().push_back(Cursor::new((*vec).clone()));
-                    self.writable(event_loop, token)
-                }
+                self.queue(event_loop, id.token(), vec)
             }
         }
     }
     
     fn ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
-        if events.is_writable() {
+        if !self.streams.contains_key(&token) && self.idents.contains_key(&ident_token(token)) {
+            return self.ident_ready(event_loop, ident_token(token), events)
+        }
+        if self.closing.contains_key(&token) {
+            if events.is_hup() || events.is_error() {
+                self.unregister_connection(&token, event_loop)
+            } else if events.is_writable() {
+                self.writable(event_loop, token)
+            }
+            return
+        }
+        let handshake_done = self.handshake(event_loop, token);
+        if handshake_done && (events.is_writable() || events.is_readable()) {
+            // Flush the messages that were queued during the handshake
             self.writable(event_loop, token)
-        } else if events.is_readable() {
-            self.readable(event_loop, token, events)
+        }
+        // Edge-triggered events may signal both at once
+        if events.is_hup() || events.is_error() || (handshake_done && events.is_readable()) {
+            // Edge-triggered, read until the socket is drained
+            while self.readable(event_loop, token, events) {}
+            // TLS may have buffered more records than were read
+            while self.streams.get(&token).map_or(false, |stream| stream.pending() > 0) {
+                self.readable(event_loop, token, events);
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<Self>, timer: Timer) {
+        match timer {
+            Timer::Ping(token) => self.check_alive(event_loop, token),
+            Timer::Registration(token) => self.check_registration(event_loop, token),
+            Timer::Lookup(token) => self.lookups_expired(event_loop, token),
+            Timer::Flood(token) => self.release_delayed(event_loop, token),
+            Timer::Close(token) => self.close_expired(event_loop, token)
         }
     }
 }
@@ -269,13 +1164,13 @@ impl MessageReader {
 
     /// Tries to re-fill the internal buffer
     ///
-    /// The returns MessageReader is an Interator over the messages that can be 
-    /// reconstructed from the internal buffer.
-    pub fn feed<R: Read>(&mut self, r: &mut R) -> io::Result<&mut MessageReader> {
+    /// Returns the number of bytes read. The MessageReader is an Interator
+    /// over the messages that can be reconstructed from the internal buffer.
+    pub fn feed<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
         use bytes::MutBuf;
         let n_bytes = try!(r.read(unsafe {&mut self.buf.mut_bytes()}));
         unsafe { self.buf.advance(n_bytes) };
-        Ok(self)
+        Ok(n_bytes)
     }
 
     /// Resets the internal error state
@@ -368,4 +1263,88 @@ impl Iterator for MessageReader {
             }
         }
     }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn tls_registration() {
+        test::run_server();
+        let mut client = test::Client::tls("tls_test");
+        client.register();
+        client.send_msg("WHOIS tls_test");
+        client.skip_until(":localhost 671 tls_test tls_test :is using a secure connection");
+        client.send_msg("JOIN #tlstest");
+        client.skip_until(":localhost 366 tls_test");
+        client.send_msg("MODE #tlstest +z");
+        client.expect_begin(":tls_test!");
+        let mut plain = test::Client::registered("tls_plain");
+        plain.send_msg("JOIN #tlstest");
+        plain.expect_begin(":localhost 489 tls_plain #tlstest");
+    }
+    #[test]
+    fn ping_timeout() {
+        test::run_timeout_server();
+        let mut victim = test::Client::connect("ping_victim", test::TIMEOUT_ADDR);
+        victim.register();
+        victim.send_msg("JOIN #pingtest");
+        victim.skip_until(":localhost 366 ping_victim");
+        let mut other = test::Client::connect("ping_other", test::TIMEOUT_ADDR);
+        other.register();
+        other.send_msg("JOIN #pingtest");
+        other.skip_until(":localhost 366 ping_other");
+        victim.expect_begin(":ping_other!");
+        // The victim does not answer the ping
+        victim.expect(":localhost PING :localhost");
+        // The other member keeps answering until it sees the victim quit
+        loop {
+            let msg = other.read_msg();
+            if msg.contains(" PING ") {
+                other.send_msg("PONG :localhost")
+            } else {
+                assert!(msg.starts_with(":ping_victim!") && msg.contains(" QUIT :Ping timeout: "), "{}", msg);
+                break
+            }
+        }
+    }
+    #[test]
+    fn registration_timeout() {
+        test::run_timeout_server();
+        let mut client = test::Client::connect("reg_timeout", test::TIMEOUT_ADDR);
+        // The ERROR line is sent before the connection is closed
+        client.skip_until("ERROR :Closing Link (Registration timeout)");
+        client.expect_closed();
+    }
+    #[test]
+    /// The host name is looked up before the registration completes
+    fn host_lookup() {
+        test::run_server();
+        let mut client = test::Client::new("lookup".to_string());
+        client.expect(":localhost NOTICE * :*** Looking up your hostname...");
+        client.send_msg("NICK lookup");
+        client.send_msg("USER lookup 0 * :Test user");
+        let result = client.read_msg();
+        assert!(result.starts_with(":localhost NOTICE * :*** "), "{}", result);
+        let welcome = client.read_msg();
+        if result.ends_with("Found your hostname") {
+            assert!(!welcome.ends_with("@127.0.0.1"), "{}", welcome);
+        } else {
+            assert!(welcome.ends_with("lookup!lookup@127.0.0.1"), "{}", welcome);
+        }
+    }
+    #[test]
+    /// Clients that send too many commands are disconnected, operators are exempt
+    fn flood_protection() {
+        test::run_server();
+        let flood: String = (0..100).map(|i| format!("PING :flood{}\r\n", i)).collect();
+        let mut client = test::Client::registered("flood_a");
+        client.send_raw(flood.as_bytes());
+        client.skip_until("ERROR :Closing Link (Excess Flood)");
+        let mut oper = test::Client::registered("flood_op");
+        oper.send_msg("OPER test secret");
+        oper.skip_until(":localhost 381 flood_op");
+        oper.send_raw(flood.as_bytes());
+        oper.skip_until(":localhost PONG localhost :flood99");
+    }
 }
\ No newline at end of file
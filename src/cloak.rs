@@ -0,0 +1,101 @@
+//! Host cloaking
+//!
+//! The cloak of a host is derived from a keyed HMAC so that it cannot be
+//! reversed without the key. Every segment is hashed separately to keep
+//! bans on address ranges and domains working:
+//!
+//! - `192.0.2.1` becomes `A1B2C3D4.E5F6A7B8.C9D0E1F2.IP` where the
+//!   segments are the hashes of `192.0.2.1`, `192.0.2` and `192.0`
+//! - `2001:db8::1` becomes `A1B2C3D4:E5F6A7B8:C9D0E1F2:IP` where the
+//!   segments are the hashes of the address, the /64 and the /48 network
+//! - `host.example.com` becomes `<prefix>-A1B2C3D4.example.com`
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Number of hash bytes used per segment
+const SEGMENT_LEN: usize = 4;
+
+/// Keyed host cloaking scheme
+#[derive(Debug, Clone)]
+pub struct Cloak {
+    key: Vec<u8>,
+    prefix: String
+}
+
+impl Cloak {
+    /// Creates a cloaking scheme with the secret `key`
+    ///
+    /// `prefix` is prepended to cloaked host names.
+    pub fn new(key: &str, prefix: &str) -> Cloak {
+        Cloak {
+            key: key.as_bytes().to_vec(),
+            prefix: prefix.to_string()
+        }
+    }
+
+    /// Returns the cloak of a host name or an IP address
+    pub fn cloak(&self, host: &str) -> String {
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            let o = ip.octets();
+            format!("{}.{}.{}.IP",
+                self.hash(host),
+                self.hash(&format!("{}.{}.{}", o[0], o[1], o[2])),
+                self.hash(&format!("{}.{}", o[0], o[1]))
+            )
+        } else if let Ok(ip) = host.parse::<Ipv6Addr>() {
+            let s = ip.segments();
+            format!("{}:{}:{}:IP",
+                self.hash(&ip.to_string()),
+                self.hash(&format!("{:x}:{:x}:{:x}:{:x}", s[0], s[1], s[2], s[3])),
+                self.hash(&format!("{:x}:{:x}:{:x}", s[0], s[1], s[2]))
+            )
+        } else {
+            let host = host.to_lowercase();
+            match host.find('.') {
+                // Keep the domain if the host is not a domain itself
+                Some(i) if host[i + 1..].contains('.') => {
+                    format!("{}-{}{}", self.prefix, self.hash(&host), &host[i..])
+                },
+                _ => format!("{}-{}", self.prefix, self.hash(&host))
+            }
+        }
+    }
+
+    /// Hashes a segment
+    fn hash(&self, segment: &str) -> String {
+        let key = PKey::hmac(&self.key).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.update(segment.as_bytes()).unwrap();
+        signer.sign_to_vec().unwrap()[..SEGMENT_LEN].iter()
+            .map(|b| format!("{:02X}", b))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cloak;
+
+    #[test]
+    fn cloak_hosts() {
+        let cloak = Cloak::new("secret", "rauta");
+        let a = cloak.cloak("192.0.2.1");
+        let b = cloak.cloak("192.0.2.2");
+        assert!(a.ends_with(".IP") && a.len() == 29);
+        // Same /24 network
+        assert_eq!(a[9..], b[9..]);
+        assert!(a[..8] != b[..8]);
+        let a = cloak.cloak("2001:db8::1");
+        let b = cloak.cloak("2001:db8::2");
+        assert!(a.ends_with(":IP"));
+        assert_eq!(a[9..], b[9..]);
+        let host = cloak.cloak("Host.example.com");
+        assert!(host.starts_with("rauta-") && host.ends_with(".example.com"));
+        assert_eq!(host, cloak.cloak("host.example.com"));
+        assert!(cloak.cloak("localhost").starts_with("rauta-"));
+        assert!(Cloak::new("other", "rauta").cloak("192.0.2.1") != cloak.cloak("192.0.2.1"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Nickname history for WHOWAS
+use std::collections::VecDeque;
+
+use time;
+
+use user::User;
+use casemapping;
+
+/// Default number of entries kept in the history
+pub const HISTORY_LEN: usize = 100;
+
+/// Information about a nickname that is no longer in use
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub nick: String,
+    pub user: String,
+    pub host: String,
+    pub realname: String,
+    /// Time the nickname was given up (seconds since the epoch)
+    pub time: i64,
+}
+
+/// Bounded nickname history
+///
+/// Once the history is full the oldest entries are dropped.
+#[derive(Debug)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize
+}
+
+impl History {
+    /// Creates a history which keeps at most `capacity` entries
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity
+        }
+    }
+
+    /// Records that the user gave up its current nickname
+    pub fn record(&mut self, user: &User) {
+        if self.capacity == 0 {
+            return
+        }
+        if self.entries.len() == self.capacity {
+            let _ = self.entries.pop_back();
+        }
+        self.entries.push_front(Entry {
+            nick: user.nick().to_string(),
+            user: user.user().to_string(),
+            host: user.public_host().to_string(),
+            realname: user.realname().to_string(),
+            time: time::get_time().sec
+        })
+    }
+
+    /// Returns the entries for `nick`, newest first
+    pub fn lookup<'a>(&'a self, nick: &'a str) -> Box<Iterator<Item=&'a Entry> + 'a> {
+        Box::new(self.entries.iter().filter(move |entry| casemapping::eq(&entry.nick, nick)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use user::User;
+
+    #[test]
+    fn bounded_history() {
+        let mut history = History::new(2);
+        for nick in ["foo", "bar", "foo"].iter() {
+            let mut user = User::new("localhost".to_string());
+            user.set_nick(nick.to_string());
+            history.record(&user);
+        }
+        assert_eq!(history.lookup("foo").count(), 1);
+        assert_eq!(history.lookup("bar").count(), 1);
+        assert_eq!(history.lookup("BAR").count(), 1);
+        assert_eq!(history.lookup("baz").count(), 0);
+    }
+}
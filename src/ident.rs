@@ -0,0 +1,185 @@
+//! Ident protocol client (RFC 1413)
+//!
+//! The ident server on the host of a client is asked for the user that
+//! owns the connection. The query is sent from the address the client
+//! connected to such that the ident server can find the connection.
+use std::io;
+use std::net::SocketAddr;
+
+use mio::{EventSet, TryRead, TryWrite};
+use mio::tcp::TcpStream;
+use net2::TcpBuilder;
+
+/// Default port of ident servers
+pub const PORT: u16 = 113;
+
+/// Maximum length of a user id that is accepted
+const USER_LEN: usize = 10;
+
+/// Maximum length of a response that is read
+const RESPONSE_LEN: usize = 1000;
+
+/// Query to the ident server of a connection
+///
+/// The query does not block, its stream has to be registered with an
+/// event loop for readable and writable events and `ready` be called on
+/// each of them. The owner is responsible for the timeout.
+pub struct Query {
+    stream: TcpStream,
+    local: SocketAddr,
+    peer: SocketAddr,
+    sent: bool,
+    response: Vec<u8>
+}
+
+impl Query {
+    /// Starts to connect to the ident server of a connection
+    ///
+    /// `local` and `peer` are the addresses of the client connection as seen
+    /// by the server, `port` is the port of the ident server. The query is
+    /// sent from the address the client connected to.
+    pub fn start(local: SocketAddr, peer: SocketAddr, port: u16) -> io::Result<Query> {
+        let builder = try!(match local {
+            SocketAddr::V4(..) => TcpBuilder::new_v4(),
+            SocketAddr::V6(..) => TcpBuilder::new_v6(),
+        });
+        try!(builder.bind(SocketAddr::new(local.ip(), 0)));
+        let stream = try!(TcpStream::connect_stream(
+            try!(builder.to_tcp_stream()), &SocketAddr::new(peer.ip(), port)
+        ));
+        Ok(Query {
+            stream: stream,
+            local: local,
+            peer: peer,
+            sent: false,
+            response: Vec::new()
+        })
+    }
+
+    /// Stream to the ident server
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Continues the query after an event of its stream
+    ///
+    /// Returns `None` while the query is in progress, otherwise the user id
+    /// if the server replied with a valid one.
+    pub fn ready(&mut self, events: EventSet) -> Option<Option<String>> {
+        if events.is_error() {
+            debug!("ident query for {} failed: {:?}", self.peer, self.stream.take_socket_error());
+            return Some(None)
+        }
+        if !self.sent {
+            if !events.is_writable() {
+                return if events.is_hup() { Some(None) } else { None }
+            }
+            let request = format!("{} , {}\r\n", self.peer.port(), self.local.port());
+            match self.stream.try_write(request.as_bytes()) {
+                Ok(Some(n)) if n == request.len() => self.sent = true,
+                Ok(None) => return None,
+                _ => return Some(None)
+            }
+        }
+        let mut buf = [0; 512];
+        loop {
+            match self.stream.try_read(&mut buf) {
+                Ok(Some(0)) => break,
+                Ok(Some(n)) => {
+                    self.response.extend_from_slice(&buf[..n]);
+                    if self.response.contains(&b'\n') || self.response.len() >= RESPONSE_LEN {
+                        break
+                    }
+                },
+                Ok(None) => return None,
+                Err(_) => return Some(None)
+            }
+        }
+        let response = String::from_utf8_lossy(&self.response);
+        let line = response.split('\n').next().unwrap_or("");
+        Some(parse_response(line, self.peer.port(), self.local.port()))
+    }
+}
+
+/// Parses a reply of the form `<port> , <port> : USERID : <os> : <user id>`
+fn parse_response(response: &str, peer_port: u16, local_port: u16) -> Option<String> {
+    let mut parts = response.trim_right().splitn(4, ':');
+    let (ports, kind, user) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(ports), Some(kind), Some(_), Some(user)) => (ports, kind, user),
+        _ => return None
+    };
+    let mut ports = ports.split(',').map(|v| v.trim().parse::<u16>());
+    match (ports.next(), ports.next()) {
+        (Some(Ok(a)), Some(Ok(b))) if a == peer_port && b == local_port => (),
+        _ => return None
+    }
+    if kind.trim() != "USERID" {
+        return None
+    }
+    let user: String = user.trim().chars().take(USER_LEN).collect();
+    if user.len() > 0 && user.chars().all(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' | '.' => true,
+        _ => false
+    }) {
+        Some(user)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::io::BufReader;
+    use std::net::{TcpListener, SocketAddr};
+    use std::thread::spawn;
+
+    use mio::{Poll, Token, EventSet, PollOpt};
+
+    use super::{Query, parse_response};
+
+    #[test]
+    fn ident_responses() {
+        assert_eq!(parse_response("6193, 23 : USERID : UNIX : stjohns\r\n", 6193, 23), Some("stjohns".to_string()));
+        assert_eq!(parse_response("6193, 23 : USERID : UNIX : stjohns", 6194, 23), None);
+        assert_eq!(parse_response("6193, 23 : ERROR : NO-USER", 6193, 23), None);
+        assert_eq!(parse_response("6193, 23 : USERID : UNIX : a b", 6193, 23), None);
+        assert_eq!(parse_response("6193, 23 : USERID : UNIX : averyverylongname", 6193, 23), Some("averyveryl".to_string()));
+    }
+
+    /// Runs a query on its own event loop
+    fn query(local: SocketAddr, peer: SocketAddr, port: u16) -> Option<String> {
+        let mut query = Query::start(local, peer, port).unwrap();
+        let mut poll = Poll::new().unwrap();
+        poll.register(query.stream(), Token(0), EventSet::all(), PollOpt::edge()).unwrap();
+        loop {
+            assert!(poll.poll(Some(5000)).unwrap() > 0, "ident query timed out");
+            let events: Vec<_> = poll.events().map(|event| event.kind).collect();
+            for events in events {
+                if let Some(result) = query.ready(events) {
+                    return result
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ident_query() {
+        // Local stand-in for the ident server of the client
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let reply = format!("{} : USERID : UNIX : alice\r\n", line.trim());
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+        });
+        let local: SocketAddr = "127.0.0.1:6667".parse().unwrap();
+        let peer: SocketAddr = "127.0.0.1:41234".parse().unwrap();
+        assert_eq!(query(local, peer, port), Some("alice".to_string()));
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        assert_eq!(query(local, peer, closed), None);
+    }
+}
@@ -17,6 +17,11 @@ extern crate bytes;
 extern crate num;
 extern crate rand;
 extern crate mio;
+extern crate net2;
+extern crate openssl;
+extern crate postgres;
+extern crate time;
+extern crate toml;
 
 pub mod net;
 pub mod services;
@@ -28,15 +33,72 @@ pub mod user;
 pub mod channel;
 pub mod misc;
 pub mod client_io;
+pub mod config;
+pub mod history;
+pub mod casemapping;
+pub mod password;
+pub mod cloak;
+pub mod ident;
+pub mod throttle;
+pub mod ban;
+pub mod sasl;
 
 #[cfg(test)]
 pub mod test;
 
 #[cfg(not(test))]
 fn main() {
-    env_logger::init().unwrap();
+    use std::env;
+    use std::io::{self, Write};
+    use std::process::{self, Command};
+    use std::os::unix::process::CommandExt;
 
-    let server = box server::Server::new("localhost");
+    env_logger::init().unwrap();
 
-    let _ = server.map(|mut s| s.run_mio()).unwrap();
-}
\ No newline at end of file
+    let config = match env::args().nth(1) {
+        Some(ref flag) if flag == "--hash-password" => {
+            // The password is read from stdin to keep it out of the process
+            // list and the shell history
+            let mut password = String::new();
+            let _ = io::stdin().read_line(&mut password);
+            let password = password.trim_right_matches(|c| c == '\r' || c == '\n');
+            if password.is_empty() || env::args().nth(2).is_some() {
+                let _ = writeln!(io::stderr(), "usage: rauta --hash-password (reads the password from stdin)");
+                process::exit(1)
+            }
+            println!("{}", password::hash(password));
+            return
+        },
+        Some(path) => match config::Config::load(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "{}: {}", path, err);
+                process::exit(1)
+            }
+        },
+        None => Default::default()
+    };
+    let restart = match server::Server::new(config) {
+        Ok(mut server) => match server.run_mio() {
+            Ok(()) => server.restart_requested(),
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "{}", err);
+                process::exit(1)
+            }
+        },
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}", err);
+            process::exit(1)
+        }
+    };
+    if restart {
+        let args: Vec<String> = env::args().skip(1).collect();
+        // exec only returns if the server could not be started again
+        let err = match env::current_exe() {
+            Ok(exe) => Command::new(exe).args(&args).exec(),
+            Err(err) => err
+        };
+        let _ = writeln!(io::stderr(), "cannot restart: {}", err);
+        process::exit(1)
+    }
+}
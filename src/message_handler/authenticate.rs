@@ -0,0 +1,238 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::AUTHENTICATE;
+use client::Client;
+use server::Server;
+use services::{self, Credentials};
+use sasl::{self, Mechanism, Session};
+use casemapping;
+use user::Status;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Handler for AUTHENTICATE message
+///
+/// `AUTHENTICATE <mechanism>`
+/// `AUTHENTICATE <base64 data>|+|*`
+///
+/// Only available while the registration is suspended by the capability
+/// negotiation and the client enabled `sasl`. The first message selects the
+/// mechanism, the following ones carry the client response, `*` aborts.
+#[derive(Debug)]
+pub struct Handler {
+    param: String
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        match message.params().next() {
+            Some(param) if !param.is_empty() => Ok(Handler {
+                param: String::from_utf8_lossy(param).into_owned()
+            }),
+            _ => Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", AUTHENTICATE), "Not enough parameters")
+            ))
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        let (negotiating, enabled, authenticated) = {
+            let info = client.info();
+            let negotiating = match info.status() {
+                Status::Negotiating(_) => true,
+                _ => false
+            };
+            (negotiating, info.has_capability("sasl"), info.account().is_some())
+        };
+        if authenticated {
+            server.send_response(&client, ERR_SASLALREADY, &["You have already authenticated using SASL"]);
+            return
+        }
+        if !negotiating || !enabled {
+            server.send_response(&client, ERR_SASLFAIL, &["SASL authentication failed"]);
+            return
+        }
+        if self.param == "*" {
+            abort(server, &client);
+            return
+        }
+        // Prevent dead-lock
+        let result = client.info_mut().sasl_mut().map(|session| (session.mechanism(), session.push(&self.param)));
+        match result {
+            None => match Mechanism::from_name(&self.param) {
+                Some(mechanism) => {
+                    {let _ = client.info_mut().set_sasl(Some(Session::new(mechanism)));}
+                    server.send_msg(&client, AUTHENTICATE, &["+"])
+                },
+                None => {
+                    server.send_response(&client, RPL_SASLMECHS, &[sasl::MECHANISMS, "are available SASL mechanisms"]);
+                    server.send_response(&client, ERR_SASLFAIL, &["SASL authentication failed"])
+                }
+            },
+            Some((_, Ok(None))) => (),
+            Some((mechanism, Ok(Some(response)))) => {
+                {let _ = client.info_mut().set_sasl(None);}
+                match authenticate(server, &client, mechanism, &response) {
+                    Some(account) => logged_in(server, &client, account),
+                    None => failed(server, &client, mechanism)
+                }
+            },
+            Some((_, Err(err))) => {
+                {let _ = client.info_mut().set_sasl(None);}
+                match err {
+                    sasl::Error::TooLong => server.send_response(&client, ERR_SASLTOOLONG, &["SASL message too long"]),
+                    sasl::Error::Invalid => server.send_response(&client, ERR_SASLFAIL, &["SASL authentication failed"])
+                }
+            }
+        }
+    }
+}
+
+/// Aborts the authentication in progress
+///
+/// Also called when the capability negotiation ends.
+pub fn abort(server: &Server, client: &Client) {
+    let session = {
+        client.info_mut().set_sasl(None)
+    };
+    if session.is_some() {
+        server.send_response(client, ERR_SASLABORTED, &["SASL authentication aborted"])
+    }
+}
+
+/// Checks the client response, returns the account name
+fn authenticate(server: &Server, client: &Client, mechanism: Mechanism, response: &[u8]) -> Option<String> {
+    let (authzid, account) = match mechanism {
+        Mechanism::Plain => match sasl::parse_plain(response) {
+            // Clients that failed too often are not checked anymore
+            Some(_) if !services::may_log_in(client) => return None,
+            Some((authzid, authcid, password)) => (
+                authzid,
+                server.authenticate(&Credentials::Password(&authcid, &password))
+            ),
+            None => return None
+        },
+        Mechanism::External => {
+            let certfp = client.info().certfp().map(|v| v.to_string());
+            match (String::from_utf8(response.to_vec()), certfp) {
+                (Ok(authzid), Some(certfp)) => (
+                    authzid,
+                    server.authenticate(&Credentials::Certificate(&certfp))
+                ),
+                _ => return None
+            }
+        }
+    };
+    // Logging in to another account than the own is not supported
+    account.and_then(|account| if authzid.is_empty() || casemapping::eq(&authzid, &account) {
+        Some(account)
+    } else {
+        None
+    })
+}
+
+/// Logs the failed authentication and replies
+///
+/// Failed passwords are counted like the ones given to NickServ.
+fn failed(server: &Server, client: &Client, mechanism: Mechanism) {
+    info!("failed SASL {} by {}", mechanism.name(), client.info().real_hostmask().as_str());
+    server.send_response(client, ERR_SASLFAIL, &["SASL authentication failed"]);
+    if mechanism == Mechanism::Plain {
+        services::login_failed(server, client)
+    }
+}
+
+/// Identifies the client to the account and replies
+fn logged_in(server: &Server, client: &Client, account: String) {
+    let mask = {
+        let mut info = client.info_mut();
+        info.set_account(Some(account.clone()));
+        info.real_hostmask().as_str().to_string()
+    };
+    info!("{} logged in as {} using SASL", mask, account);
+    server.send_response(client, RPL_LOGGEDIN, &[
+        &*mask, &*account, &*format!("You are now logged in as {}", account)
+    ]);
+    server.send_response(client, RPL_SASLSUCCESS, &["SASL authentication successful"])
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn sasl_plain() {
+        test::run_server();
+        let mut owner = test::Client::registered("sasl_acct");
+        owner.send_msg("PRIVMSG NickServ :REGISTER secret sasl@example.com");
+        owner.expect(":NickServ!NickServ@localhost NOTICE sasl_acct :Nick sasl_acct has been registered to you, you are now identified.");
+        owner.send_msg("PRIVMSG NickServ :CERT ADD");
+        owner.expect(":NickServ!NickServ@localhost NOTICE sasl_acct :You are not using a client certificate.");
+        owner.send_msg("QUIT");
+
+        let mut client = test::Client::new("sasl_usr".to_string());
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.skip_until(":localhost 904 * :SASL authentication failed");
+        client.send_msg("CAP LS");
+        client.expect(":localhost CAP * LS :sasl");
+        client.send_msg("CAP REQ :sasl foo");
+        client.expect(":localhost CAP * NAK :sasl foo");
+        client.send_msg("CAP REQ :sasl");
+        client.expect(":localhost CAP * ACK :sasl");
+        client.send_msg("CAP LIST");
+        client.expect(":localhost CAP * LIST :sasl");
+        client.send_msg("NICK sasl_usr");
+        client.send_msg("USER sasl_usr 0 * :Test user");
+        client.send_msg("AUTHENTICATE SCRAM-SHA-256");
+        client.expect(":localhost 908 sasl_usr PLAIN,EXTERNAL :are available SASL mechanisms");
+        client.expect(":localhost 904 sasl_usr :SASL authentication failed");
+        client.send_msg("AUTHENTICATE EXTERNAL");
+        client.expect(":localhost AUTHENTICATE :+");
+        client.send_msg("AUTHENTICATE +");
+        client.expect(":localhost 904 sasl_usr :SASL authentication failed");
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.expect(":localhost AUTHENTICATE :+");
+        client.send_msg("AUTHENTICATE *");
+        client.expect(":localhost 906 sasl_usr :SASL authentication aborted");
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.expect(":localhost AUTHENTICATE :+");
+        // \0sasl_acct\0wrongpass
+        client.send_msg("AUTHENTICATE AHNhc2xfYWNjdAB3cm9uZ3Bhc3M=");
+        client.expect(":localhost 904 sasl_usr :SASL authentication failed");
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.expect(":localhost AUTHENTICATE :+");
+        // sasl_other\0sasl_acct\0secret
+        client.send_msg("AUTHENTICATE c2FzbF9vdGhlcgBzYXNsX2FjY3QAc2VjcmV0");
+        client.expect(":localhost 904 sasl_usr :SASL authentication failed");
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.expect(":localhost AUTHENTICATE :+");
+        // \0sasl_acct\0secret
+        client.send_msg("AUTHENTICATE AHNhc2xfYWNjdABzZWNyZXQ=");
+        client.expect_begin(":localhost 900 sasl_usr sasl_usr!");
+        client.expect(":localhost 903 sasl_usr :SASL authentication successful");
+        client.send_msg("AUTHENTICATE PLAIN");
+        client.expect(":localhost 907 sasl_usr :You have already authenticated using SASL");
+        client.send_msg("CAP END");
+        client.skip_until(":localhost 001 sasl_usr");
+        client.skip_burst();
+        client.send_msg("WHOIS sasl_usr");
+        client.skip_until(":localhost 330 sasl_usr sasl_usr sasl_acct :is logged in as");
+    }
+
+    #[test]
+    fn sasl_plain_failures() {
+        test::run_server();
+        let mut client = test::Client::new("sasl_guess".to_string());
+        client.send_msg("CAP REQ :sasl");
+        client.skip_until(":localhost CAP * ACK :sasl");
+        client.send_msg("NICK sasl_guess");
+        client.send_msg("USER sasl_guess 0 * :Test user");
+        for _ in 0..5 {
+            client.send_msg("AUTHENTICATE PLAIN");
+            client.expect(":localhost AUTHENTICATE :+");
+            // \0sasl_acct\0wrongpass
+            client.send_msg("AUTHENTICATE AHNhc2xfYWNjdAB3cm9uZ3Bhc3M=");
+            client.expect(":localhost 904 sasl_guess :SASL authentication failed");
+        }
+        client.expect("ERROR :Closing Link (Too many failed login attempts)");
+    }
+}
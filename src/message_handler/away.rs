@@ -0,0 +1,66 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use client::Client;
+use server::Server;
+use user;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Handler for AWAY message
+///
+/// `AWAY [ <text> ]`
+///
+/// An empty or missing text marks the user as being present again.
+/// Away messages longer than `AWAYLEN` are truncated.
+#[derive(Debug)]
+pub struct Handler {
+    away: Option<String>
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let away = match message.params().next() {
+            Some(text) if text.len() > 0 => Some(
+                String::from_utf8_lossy(text).chars().take(user::AWAY_LEN).collect()
+            ),
+            _ => None
+        };
+        Ok(Handler {
+            away: away
+        })
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        let is_away = self.away.is_some();
+        {client.info_mut().set_away(self.away)}
+        if is_away {
+            server.send_response(&client, RPL_NOWAWAY, &["You have been marked as being away"])
+        } else {
+            server.send_response(&client, RPL_UNAWAY, &["You are no longer marked as being away"])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn away() {
+        test::run_server();
+        let mut away = test::Client::registered("away_a");
+        let mut client = test::Client::registered("away_b");
+        away.send_msg("JOIN #awaytest");
+        away.skip_until(":localhost 366 away_a");
+        away.send_msg("AWAY :gone fishing");
+        away.expect(":localhost 306 away_a :You have been marked as being away");
+        client.send_msg("PRIVMSG away_a :hello");
+        client.expect(":localhost 301 away_b away_a :gone fishing");
+        client.send_msg("WHO #awaytest");
+        assert!(client.read_msg().contains(" away_a G@ :0 "));
+        client.expect_begin(":localhost 315 away_b #awaytest ");
+        away.send_msg("AWAY");
+        away.expect_begin(":away_b!");
+        away.expect(":localhost 305 away_a :You are no longer marked as being away");
+        client.send_msg("WHO #awaytest");
+        assert!(client.read_msg().contains(" away_a H@ :0 "));
+    }
+}
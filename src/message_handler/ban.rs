@@ -0,0 +1,177 @@
+use time;
+
+use protocol::{ResponseCode, Message, Command};
+use protocol::ResponseCode::*;
+use protocol::Command::{KLINE, UNKLINE, DLINE, UNDLINE, NOTICE};
+use client::Client;
+use server::Server;
+use ban::{self, Ban};
+use net::Cidr;
+
+use super::{MessageHandler, ErrorMessage, is_operator};
+
+/// Handler for server-wide bans
+///
+/// `KLINE [ <minutes> ] <user@host> [ <reason> ]`
+/// `UNKLINE <user@host>`
+/// `DLINE [ <minutes> ] <address range> [ <reason> ]`
+/// `UNDLINE <address range>`
+///
+/// Bans without a duration are permanent. Users that match a new ban are
+/// disconnected right away.
+#[derive(Debug)]
+pub struct Handler {
+    cmd: Command,
+    /// Duration in minutes
+    duration: Option<i64>,
+    target: String,
+    reason: String
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let cmd = message.command().unwrap();
+        let mut params = message.params().map(|v| String::from_utf8_lossy(v).into_owned()).peekable();
+        let duration = match cmd {
+            KLINE | DLINE => match params.peek().and_then(|v| v.parse::<u32>().ok()) {
+                Some(minutes) => {
+                    let _ = params.next();
+                    if minutes > 0 { Some(minutes as i64) } else { None }
+                },
+                None => None
+            },
+            _ => None
+        };
+        match params.next() {
+            Some(target) => Ok(Handler {
+                cmd: cmd,
+                duration: duration,
+                target: target,
+                reason: params.next().unwrap_or("No reason".to_string())
+            }),
+            None => Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", cmd), "Not enough parameters")
+            ))
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if !is_operator(server, &client) {
+            return
+        }
+        let nick = client.nick().to_string();
+        let now = time::get_time().sec;
+        let kind = match self.cmd {
+            KLINE | UNKLINE => "K-line",
+            _ => "D-line"
+        };
+        let target = match self.cmd {
+            KLINE | UNKLINE => ban::kline_mask(&self.target),
+            _ => Cidr::parse(&self.target).map(|range| range.to_string())
+        };
+        let target = match target {
+            Some(target) => target,
+            None => {
+                server.send_msg(&client, NOTICE, &[
+                    &*nick, &*format!("Invalid {} mask: {}", kind, self.target)
+                ]);
+                return
+            }
+        };
+        let _ = server.bans_mut().expire(now);
+        let notice = match self.cmd {
+            KLINE | DLINE => {
+                let expires = self.duration.map(|minutes| now + minutes * 60);
+                if self.cmd == KLINE {
+                    server.bans_mut().add_kline(Ban {
+                        target: target.clone(),
+                        reason: self.reason.clone(),
+                        set_by: nick.clone(),
+                        set_at: now,
+                        expires: expires
+                    })
+                } else {
+                    server.bans_mut().add_dline(Ban {
+                        target: Cidr::parse(&target).unwrap(),
+                        reason: self.reason.clone(),
+                        set_by: nick.clone(),
+                        set_at: now,
+                        expires: expires
+                    })
+                }
+                match self.duration {
+                    Some(minutes) => format!(
+                        "{} added temporary {} min. {} for [{}] [{}]", nick, minutes, kind, target, self.reason
+                    ),
+                    None => format!("{} added {} for [{}] [{}]", nick, kind, target, self.reason)
+                }
+            },
+            _ => {
+                let removed = if self.cmd == UNKLINE {
+                    server.bans_mut().remove_kline(&target)
+                } else {
+                    server.bans_mut().remove_dline(Cidr::parse(&target).unwrap())
+                };
+                if !removed {
+                    server.send_msg(&client, NOTICE, &[&*nick, &*format!("No {} for {}", kind, target)]);
+                    return
+                }
+                format!("{} has removed the {} for [{}]", nick, kind, target)
+            }
+        };
+        server.save_bans();
+        info!("{}", notice);
+        server.send_msg(&client, NOTICE, &[&*nick, &*notice]);
+        server.send_server_notice(&notice);
+        server.enforce_bans()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn kline_dline() {
+        test::run_server();
+        let mut user = test::Client::registered("kline_usr");
+        let mut op = test::Client::registered("kline_op");
+        user.send_msg("KLINE *@example.com :spam");
+        user.expect(":localhost 481 kline_usr :Permission Denied- You're not an IRC operator");
+        op.send_msg("OPER test secret");
+        op.skip_until(":kline_op!");
+        op.send_msg("KLINE");
+        op.expect(":localhost 461 kline_op KLINE :Not enough parameters");
+        op.send_msg("KLINE *@*");
+        op.expect(":localhost NOTICE kline_op :Invalid K-line mask: *@*");
+        op.send_msg("KLINE 10 kline_usr@127.0.0.1 :spam");
+        op.expect(":localhost NOTICE kline_op :kline_op added temporary 10 min. K-line for [kline_usr@127.0.0.1] [spam]");
+        user.expect(":localhost 465 kline_usr :You are banned from this server- spam");
+        user.expect("ERROR :Closing Link (K-lined (spam))");
+        op.send_msg("STATS k");
+        op.expect(":localhost 216 kline_op K 127.0.0.1 * kline_usr :spam");
+        op.expect(":localhost 219 kline_op k :End of STATS report");
+        let mut banned = test::Client::new("kline_new".to_string());
+        banned.send_msg("NICK kline_new");
+        banned.send_msg("USER kline_usr 0 * :Test user");
+        banned.skip_until(":localhost 465 kline_new");
+        banned.expect("ERROR :Closing Link (K-lined (spam))");
+        op.send_msg("UNKLINE kline_usr@127.0.0.1");
+        op.expect(":localhost NOTICE kline_op :kline_op has removed the K-line for [kline_usr@127.0.0.1]");
+        op.send_msg("UNKLINE kline_usr@127.0.0.1");
+        op.expect(":localhost NOTICE kline_op :No K-line for kline_usr@127.0.0.1");
+        let mut allowed = test::Client::new("kline_ok".to_string());
+        allowed.send_msg("NICK kline_ok");
+        allowed.send_msg("USER kline_usr 0 * :Test user");
+        allowed.skip_until(":localhost 001 kline_ok");
+        op.send_msg("DLINE 127.0.0.4 :bot net");
+        op.expect(":localhost NOTICE kline_op :kline_op added D-line for [127.0.0.4/32] [bot net]");
+        let mut refused = test::Client::from_addr("dline_usr", "127.0.0.4");
+        refused.expect("ERROR :Closing Link (D-lined (bot net))");
+        op.send_msg("STATS d");
+        op.expect(":localhost 225 kline_op D 127.0.0.4/32 :bot net");
+        op.expect(":localhost 219 kline_op d :End of STATS report");
+        op.send_msg("UNDLINE 127.0.0.4");
+        op.expect(":localhost NOTICE kline_op :kline_op has removed the D-line for [127.0.0.4/32]");
+        test::Client::from_addr("dline_usr", "127.0.0.4").register();
+    }
+}
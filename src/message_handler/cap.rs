@@ -1,12 +1,15 @@
 use std::ascii::AsciiExt;
 use std::ops::Deref;
+use std::str;
 use protocol::{ResponseCode, Message};
 use protocol::ResponseCode::*;
 use protocol::Command::CAP;
 use client::Client;
 use server::Server;
 use super::{MessageHandler, ErrorMessage};
+use super::authenticate;
 use user;
+use sasl;
 
 /// Handler for CAP message
 ///
@@ -17,6 +20,17 @@ pub struct Handler {
     args: Option<usize>
 }
 
+/// Capabilities that are supported by the server
+///
+/// `sasl` is only offered if the services are enabled.
+fn capabilities(server: &Server) -> Vec<&'static str> {
+    let mut capabilities = Vec::new();
+    if server.sasl_available() {
+        capabilities.push("sasl")
+    }
+    capabilities
+}
+
 /// CAP subcommands
 enum Subcommand {
     LS,
@@ -100,23 +114,50 @@ impl MessageHandler for Handler {
         })
     }
     fn invoke(self, server: &mut Server, client: Client) {
-        // TODO CAP LS stop also client registration!!
         use self::Subcommand::*;
+        let nick = client.nick().to_string();
         match self.subcmd() {
             LS => {
                 suspend_registration(&client);
-                server.send_msg(&client, CAP, &[&*client.nick(), &*LS])
+                // Version 302 clients get the values of the capabilities
+                let version = self.args.and_then(|args| self.msg.params().nth(args))
+                    .and_then(|v| str::from_utf8(v).ok())
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .unwrap_or(0);
+                let capabilities: Vec<String> = capabilities(server).into_iter().map(|name| match name {
+                    "sasl" if version >= 302 => format!("sasl={}", sasl::MECHANISMS),
+                    name => name.to_string()
+                }).collect();
+                server.send_msg(&client, CAP, &[&*nick, &*LS, &*capabilities.join(" ")])
+            },
+            LIST => {
+                let enabled = client.info().capabilities().join(" ");
+                server.send_msg(&client, CAP, &[&*nick, &*LIST, &*enabled])
             },
-            LIST => server.send_msg(&client, CAP, &[&*client.nick(), &*LIST]),
             REQ => {
                 suspend_registration(&client);
-                if let Some(args) = self.args {
-                    server.send_raw_msg(&client, CAP, &[client.nick().as_bytes(), NAK.as_bytes(), self.msg.params().nth(args).unwrap()])
+                let requested = self.args.and_then(|args| self.msg.params().nth(args))
+                    .map(|v| String::from_utf8_lossy(v).into_owned())
+                    .unwrap_or(String::new());
+                // The capabilities are only changed if all of them are supported
+                let supported = capabilities(server);
+                let changes: Vec<(&str, bool)> = requested.split_whitespace().map(|name| {
+                    if name.starts_with('-') { (&name[1..], false) } else { (name, true) }
+                }).collect();
+                if !changes.is_empty() && changes.iter().all(|&(name, _)| supported.contains(&name)) {
+                    {
+                        let mut info = client.info_mut();
+                        for &(name, enabled) in changes.iter() {
+                            info.set_capability(name, enabled)
+                        }
+                    }
+                    server.send_msg(&client, CAP, &[&*nick, &*ACK, &*requested])
                 } else {
-                    server.send_msg(&client, CAP, &[&*client.nick(), &*NAK])
+                    server.send_msg(&client, CAP, &[&*nick, &*NAK, &*requested])
                 }
             }
             END => {
+                authenticate::abort(server, &client);
                 if continue_registration(&client) {
                     server.register(&client)
                 }
@@ -66,7 +66,7 @@ impl MessageHandler for Handler {
     }
     fn invoke(self, server: &mut Server, client: Client) {
         if let Some(target) = server.client_with_name(self.nick()) {
-            if let Some(channel) = server.channels().get(self.channel()) {
+            if let Some(channel) = server.channel_with_name(self.channel()) {
                 let target = target.clone();
                 let _ = channel.with_ref_mut(move |channel| {
                     if if let Some(member) = channel.member_with_id(client.id()) {
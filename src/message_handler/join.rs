@@ -10,6 +10,7 @@ use client::{Client, MessageOrigin};
 use server::Server;
 use channel::{Channel, Member};
 use misc;
+use casemapping;
 
 use super::{MessageHandler, ErrorMessage, CommaSeparated, ParseError};
 
@@ -60,19 +61,20 @@ impl MessageHandler for Handler {
         )
     }
     fn invoke(self, server: &mut Server, client: Client) {
-        use channel::ChannelMode::*;
         let tx = server.tx().clone();
         let msg = self.msg;
         let mut passwords = self.passwords.iter(msg.params());
+        let default_modes = server.default_channel_modes().to_vec();
         for channel in self.channels.iter(msg.params()) {
             let member = Member::new(client.clone());
             let password = passwords.next().map(|v| v.to_vec());
-            let _ = match server.channels_mut().entry(channel.to_string()) {
+            let _ = match server.channels_mut().entry(casemapping::lower(channel)) {
                 Occupied(entry) => entry.into_mut(),
                 Vacant(entry) => {
                     let mut channel = Channel::new(channel.to_string());
-                    channel.add_flag(TopicProtect);
-                    channel.add_flag(MemberOnly);
+                    for &mode in default_modes.iter() {
+                        channel.add_flag(mode);
+                    }
                     entry.insert(channel.listen(tx.clone()))
                 }
             }.with_ref_mut(move |channel| {
@@ -119,6 +121,13 @@ fn handle_join(channel: &mut Channel, mut member: Member, password: Option<Vec<u
         );
         return
     }
+    if channel.has_flag(SecureOnly) && !member.client().info().is_secure() {
+        // Member not connected via TLS
+        member.send_response(ERR_SECUREONLYCHAN,
+            &[channel.name(), "Cannot join channel; SSL users only (+z)"]
+        );
+        return
+    }
     if channel.has_flag(UserLimit)
        && channel.limit().map_or(false, |limit| channel.member_count() + 1 >= limit) {
         // User limit reached
@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::KICK;
+use client::{Client, MessageOrigin};
+use server::Server;
+use misc;
+
+use super::{MessageHandler, ErrorMessage, CommaSeparated, ParseError};
+
+/// Handler for KICK message
+///
+/// `KICK <channel> *( "," <channel> ) <user> *( "," <user> ) [<comment>]`
+///
+/// Either one channel and several users are given or a user for each channel.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message,
+    channels: CommaSeparated<str>,
+    users: CommaSeparated<str>,
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let channels = match CommaSeparated::verify(misc::verify_channel, message.params(), 0) {
+            Ok(channels) => channels,
+            Err(ParseError::Malformed(channel_name)) => return Err((
+                ERR_NOSUCHCHANNEL,
+                ErrorMessage::WithSubject(
+                    String::from_utf8_lossy(channel_name).into_owned(),
+                    "Invalid channel name"
+                )
+            )),
+            Err(ParseError::TooMany) => return Err((
+                ERR_TOOMANYTARGETS,
+                ErrorMessage::WithSubject(
+                    format!("{}", KICK),
+                    "Number of targets is limited to 10"
+                )
+            )),
+            Err(ParseError::Missing) => return Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", KICK), "No channel name given")
+            )),
+        };
+        let users = match CommaSeparated::verify(misc::verify_nick, message.params(), 1) {
+            Ok(users) => users,
+            Err(ParseError::Malformed(nick)) => return Err((
+                ERR_NOSUCHNICK,
+                ErrorMessage::WithSubject(
+                    String::from_utf8_lossy(nick).into_owned(),
+                    "No such nick/channel"
+                )
+            )),
+            Err(ParseError::TooMany) => return Err((
+                ERR_TOOMANYTARGETS,
+                ErrorMessage::WithSubject(
+                    format!("{}", KICK),
+                    "Number of targets is limited to 10"
+                )
+            )),
+            Err(ParseError::Missing) => return Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", KICK), "No user given")
+            )),
+        };
+        let n_channels = channels.iter(message.params()).count();
+        if n_channels > 1 && n_channels != users.iter(message.params()).count() {
+            return Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(
+                    format!("{}", KICK),
+                    "Number of channels and users does not match"
+                )
+            ))
+        }
+        Ok(Handler {
+            msg: message,
+            channels: channels,
+            users: users,
+        })
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        let comment = match self.msg.params().nth(2) {
+            Some(comment) => comment.to_vec(),
+            None => client.nick().as_bytes().to_vec()
+        };
+        let channels: Vec<&str> = self.channels.iter(self.msg.params()).collect();
+        for (i, nick) in self.users.iter(self.msg.params()).enumerate() {
+            let chan_name = if channels.len() == 1 { channels[0] } else { channels[i] };
+            if let Some(channel) = server.channel_with_name(chan_name) {
+                let client = client.clone();
+                let nick = nick.to_string();
+                let comment = comment.clone();
+                let _ = channel.with_ref_mut(move |channel| {
+                    match channel.member_with_id(client.id()) {
+                        Some(member) => if !member.is_op() {
+                            member.send_response(
+                                ERR_CHANOPRIVSNEEDED,
+                                &[channel.name(), "You're not channel operator"]
+                            );
+                            return
+                        },
+                        None => {
+                            client.send_response(
+                                ERR_NOTONCHANNEL,
+                                &[channel.name(), "You're not on that channel"]
+                            );
+                            return
+                        }
+                    }
+                    let id = match channel.member_with_nick(&nick) {
+                        Some(member) => member.id(),
+                        None => {
+                            client.send_response(
+                                ERR_USERNOTINCHANNEL,
+                                &[&*nick, channel.name(), "They aren't on that channel"]
+                            );
+                            return
+                        }
+                    };
+                    let msg = Arc::new(client.build_raw_msg(
+                        KICK,
+                        &[channel.name().as_bytes(), nick.as_bytes(), &*comment],
+                        MessageOrigin::User
+                    ));
+                    channel.broadcast_raw(msg);
+                    channel.remove_member(&id);
+                });
+            } else {
+                client.send_response(
+                    ERR_NOSUCHCHANNEL,
+                    &[chan_name, "No such channel"]
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn kick() {
+        test::run_server();
+        let mut op = test::Client::registered("kick_op");
+        let mut user = test::Client::registered("kick_usr");
+        op.send_msg("JOIN #kicktest");
+        op.skip_until(":localhost 366 kick_op");
+        user.send_msg("JOIN #kicktest");
+        user.skip_until(":localhost 366 kick_usr");
+        op.expect_begin(":kick_usr!");
+        user.send_msg("KICK #kicktest kick_op");
+        user.expect(":localhost 482 kick_usr #kicktest :You're not channel operator");
+        op.send_msg("KICK #kicktest nobody");
+        op.expect(":localhost 441 kick_op nobody #kicktest :They aren't on that channel");
+        op.send_msg("KICK #kicktest kick_usr :bye");
+        assert!(op.read_msg().ends_with(" KICK #kicktest kick_usr :bye"));
+        assert!(user.read_msg().ends_with(" KICK #kicktest kick_usr :bye"));
+        user.send_msg("KICK #kicktest kick_op");
+        user.expect(":localhost 442 kick_usr #kicktest :You're not on that channel");
+    }
+}
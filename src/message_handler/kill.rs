@@ -0,0 +1,82 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::KILL;
+use client::{Client, MessageOrigin};
+use server::Server;
+
+use super::{MessageHandler, ErrorMessage, is_operator};
+
+/// Handler for KILL message
+///
+/// `KILL <nickname> <comment>`
+///
+/// Only IRC operators may disconnect other users.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        if message.params().count() < 2 {
+            Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", KILL), "Not enough parameters")
+            ))
+        } else {
+            Ok(Handler {
+                msg: message
+            })
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if !is_operator(server, &client) {
+            return
+        }
+        let mut params = self.msg.params();
+        let nick = String::from_utf8_lossy(params.next().unwrap());
+        let comment = String::from_utf8_lossy(params.next().unwrap());
+        let target = match server.client_with_name(&nick) {
+            Some(target) => target.clone(),
+            None => {
+                server.send_response(&client, ERR_NOSUCHNICK, &[&*nick, "No such nick/channel"]);
+                return
+            }
+        };
+        let killer = client.nick().to_string();
+        let reason = format!("Killed ({} ({}))", killer, comment);
+        info!("{} killed {}: {}", killer, nick, comment);
+        server.send_server_notice(&format!(
+            "Received KILL message for {} from {} ({})", nick, killer, comment
+        ));
+        let target_nick = target.nick().to_string();
+        target.send_msg_from(KILL, &[&*target_nick, &*comment], &client);
+        target.send_raw(format!("ERROR :Closing Link ({})\r\n", reason).into_bytes());
+        server.quit_client(&target, Some(reason.as_bytes()));
+        // The killer is not necessarily in a channel with the target
+        if target.id() != client.id() {
+            client.send_msg(KILL, &[&*target_nick, &*comment], MessageOrigin::User);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn kill() {
+        test::run_server();
+        let mut user = test::Client::registered("kill_usr");
+        let mut op = test::Client::registered("kill_op");
+        user.send_msg("KILL kill_op :bye");
+        user.expect(":localhost 481 kill_usr :Permission Denied- You're not an IRC operator");
+        op.send_msg("OPER test secret");
+        op.skip_until(":kill_op!");
+        op.send_msg("KILL nobody :bye");
+        op.expect(":localhost 401 kill_op nobody :No such nick/channel");
+        op.send_msg("KILL kill_usr :bye");
+        assert!(user.read_msg().ends_with(" KILL kill_usr :bye"));
+        user.expect("ERROR :Closing Link (Killed (kill_op (bye)))");
+        assert!(op.read_msg().ends_with(" KILL kill_usr :bye"));
+    }
+}
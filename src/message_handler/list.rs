@@ -0,0 +1,212 @@
+use std::str;
+use std::sync::Arc;
+
+use time;
+
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use client::Client;
+use server::Server;
+use channel::Channel;
+use misc;
+use casemapping;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Filters supported by LIST, advertised as `ELIST`
+pub const ELIST: &'static str = "CMNTU";
+
+/// Handler for LIST message
+///
+/// `LIST [ <channel> *( "," <channel> ) [ <target> ] ]`
+///
+/// Instead of channel names the following filters can be given:
+///
+/// - `<mask>` and `!<mask>`: channel name matches (does not match) the mask
+/// - `>n` and `<n`: channel has more (less) than n users
+/// - `C>n` and `C<n`: channel was created more (less) than n minutes ago
+/// - `T>n` and `T<n`: topic was set more (less) than n minutes ago
+///
+/// Secret channels are only listed to their members, private channels are
+/// listed as `Prv` without a topic.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message,
+    names: Vec<String>,
+    filters: Vec<Filter>,
+}
+
+/// LIST filter
+///
+/// The masks are stored in lower case to match independent of the case.
+#[derive(Debug, Clone)]
+enum Filter {
+    Mask(String),
+    NotMask(String),
+    MoreUsers(usize),
+    LessUsers(usize),
+    CreatedBefore(i64),
+    CreatedWithin(i64),
+    TopicBefore(i64),
+    TopicWithin(i64),
+}
+
+impl Filter {
+    fn parse(filter: &str) -> Option<Filter> {
+        use self::Filter::*;
+        let minutes = |v: &str| v.parse::<i64>().ok().map(|v| v * 60);
+        let count = |v: &str| v.parse::<usize>().ok();
+        if filter.starts_with(">") {
+            count(&filter[1..]).map(MoreUsers)
+        } else if filter.starts_with("<") {
+            count(&filter[1..]).map(LessUsers)
+        } else if filter.starts_with("C>") {
+            minutes(&filter[2..]).map(CreatedBefore)
+        } else if filter.starts_with("C<") {
+            minutes(&filter[2..]).map(CreatedWithin)
+        } else if filter.starts_with("T>") {
+            minutes(&filter[2..]).map(TopicBefore)
+        } else if filter.starts_with("T<") {
+            minutes(&filter[2..]).map(TopicWithin)
+        } else if filter.starts_with("!") {
+            Some(NotMask(casemapping::lower(&filter[1..])))
+        } else {
+            Some(Mask(casemapping::lower(filter)))
+        }
+    }
+
+    fn matches(&self, channel: &Channel, now: i64) -> bool {
+        use self::Filter::*;
+        match *self {
+            Mask(ref mask) => misc::match_mask(mask, &casemapping::lower(channel.name())),
+            NotMask(ref mask) => !misc::match_mask(mask, &casemapping::lower(channel.name())),
+            MoreUsers(n) => channel.member_count() > n,
+            LessUsers(n) => channel.member_count() < n,
+            CreatedBefore(age) => now - channel.created() > age,
+            CreatedWithin(age) => now - channel.created() < age,
+            TopicBefore(age) => channel.topic_time().map_or(false, |t| now - t > age),
+            TopicWithin(age) => channel.topic_time().map_or(false, |t| now - t < age),
+        }
+    }
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let mut names = Vec::new();
+        let mut filters = Vec::new();
+        if let Some(param) = message.params().next() {
+            for item in param.split(|c| *c == b',') {
+                match str::from_utf8(item) {
+                    Ok(item) if !item.contains('*') && misc::valid_channel(item) => {
+                        names.push(item.to_string())
+                    },
+                    Ok(item) if item.len() > 0 => match Filter::parse(item) {
+                        Some(filter) => filters.push(filter),
+                        None => () // ignore unknown filters
+                    },
+                    _ => ()
+                }
+            }
+        }
+        Ok(Handler {
+            msg: message,
+            names: names,
+            filters: filters,
+        })
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if let Some(target) = self.msg.params().nth(1) {
+            if target != server.host().as_bytes() {
+                server.send_response(&client, ERR_NOSUCHSERVER, &[
+                    &*String::from_utf8_lossy(target), "No such server"
+                ]);
+                return
+            }
+        }
+        let filters = Arc::new(self.filters);
+        let sender = Arc::new(ListSender {
+            client: client,
+            now: time::get_time().sec
+        });
+        let channels: Vec<_> = if self.names.len() > 0 {
+            self.names.iter().filter_map(|name| server.channel_with_name(name)).collect()
+        } else {
+            server.channels().values().collect()
+        };
+        for channel in channels {
+            let sender = sender.clone();
+            let filters = filters.clone();
+            let _ = channel.with_ref(move |channel| {
+                if filters.iter().all(|filter| filter.matches(channel, sender.now)) {
+                    sender.feed_channel(channel)
+                }
+            });
+        }
+        // RPL_LISTEND is sent when the last channel dropped the sender
+    }
+}
+
+/// Sends the channel list to a client
+///
+/// The sender is shared with the channels, `RPL_LISTEND` is sent when
+/// the last reference is dropped.
+struct ListSender {
+    client: Client,
+    now: i64
+}
+
+impl ListSender {
+    fn feed_channel(&self, channel: &Channel) {
+        let count = channel.member_count().to_string();
+        if channel.is_member(&self.client) {
+            self.client.send_response(RPL_LIST, &[channel.name(), &*count, channel.topic()])
+        } else if channel.is_secret() {
+            // Secret channels are not visible from the outside
+        } else if channel.is_private() {
+            self.client.send_response(RPL_LIST, &["Prv", &*count, ""])
+        } else {
+            self.client.send_response(RPL_LIST, &[channel.name(), &*count, channel.topic()])
+        }
+    }
+}
+
+impl Drop for ListSender {
+    fn drop(&mut self) {
+        self.client.send_response(RPL_LISTEND, &["End of LIST"])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn list() {
+        test::run_server();
+        let mut op = test::Client::registered("list_op");
+        op.send_msg("JOIN #listtest1");
+        op.skip_until(":localhost 366 list_op");
+        op.send_msg("JOIN #listtest2");
+        op.skip_until(":localhost 366 list_op");
+        op.send_msg("TOPIC #listtest1 :hello");
+        op.expect_begin(":list_op!");
+        op.send_msg("MODE #listtest2 +s");
+        op.expect_begin(":list_op!");
+        let mut client = test::Client::registered("list_usr");
+        client.send_msg("LIST #listtest*");
+        client.expect(":localhost 322 list_usr #listtest1 1 :hello");
+        client.expect(":localhost 323 list_usr :End of LIST");
+        client.send_msg("LIST #ListTest*");
+        client.expect(":localhost 322 list_usr #listtest1 1 :hello");
+        client.expect(":localhost 323 list_usr :End of LIST");
+        client.send_msg("LIST #listtest*,!#LISTTEST1*");
+        client.expect(":localhost 323 list_usr :End of LIST");
+        client.send_msg("LIST #listtest*,<1");
+        client.expect(":localhost 323 list_usr :End of LIST");
+        client.send_msg("LIST #listtest*,>0,T<5");
+        client.expect(":localhost 322 list_usr #listtest1 1 :hello");
+        client.expect(":localhost 323 list_usr :End of LIST");
+        op.send_msg("LIST #listtest2");
+        op.expect(":localhost 322 list_op #listtest2 1 :");
+        op.expect(":localhost 323 list_op :End of LIST");
+    }
+}
@@ -9,17 +9,36 @@ use server::Server;
 use client::Client;
 
 mod cap;
+mod authenticate;
+mod away;
 mod nick;
 mod user;
 mod quit;
 mod part;
+mod kick;
 mod join;
 mod invite;
 mod topic;
 mod mode;
 mod who;
+mod whois;
+mod whowas;
 mod names;
+mod list;
 mod privmsg;
+mod ping;
+mod server_query;
+mod oper;
+mod kill;
+mod ban;
+mod wallops;
+mod server_control;
+
+/// Maximum number of targets in a comma separated list
+pub const MAX_TARGETS: usize = 10;
+
+pub use self::list::ELIST;
+pub use self::nick::force_nick;
 
 /// Message handler trait
 pub trait MessageHandler: Sized {
@@ -48,7 +67,7 @@ enum OnError {
 /// Parses a and verifies a comma separated list
 pub struct CommaSeparated<T: ?Sized> {
     index: usize,
-    parameters: [Range<usize>; 10],
+    parameters: [Range<usize>; MAX_TARGETS],
     _phantom: PhantomData<Box<T>>
 }
 
@@ -73,8 +92,8 @@ impl<T: ?Sized> CommaSeparated<T> {
             let mut i = 0;
             for param in params.split(|c| *c == b',') {
                 let len = param.len();
-                if len > parameters.len() { match on_error {
-                        OnError::Skip => (),
+                if i == parameters.len() { match on_error {
+                        OnError::Skip => break,
                         OnError::Fail => return Err(ParseError::TooMany)
                 }}
                 match verify(param) {
@@ -200,23 +219,73 @@ pub fn invoke(message: Message, server: &mut Server, client: Client) {
             }
         },)*
         Some(Command::RESPONSE(_)) => (), // ignore responses from clients
+        Some(Command::ERROR) => (), // only sent by servers
         None => ()
     }
 }
 }}
 
+/// Checks whether the client is an IRC operator and replies with
+/// `ERR_NOPRIVILEGES` if it is not
+fn is_operator(server: &Server, client: &Client) -> bool {
+    let is_operator = client.info().is_operator();
+    if !is_operator {
+        server.send_response(client, ResponseCode::ERR_NOPRIVILEGES, &[
+            "Permission Denied- You're not an IRC operator"
+        ]);
+    }
+    is_operator
+}
+
+/// Commands that accept a list of targets together with the
+/// maximum number of targets per command
+pub fn target_limits() -> Vec<(Command, usize)> {
+    vec![
+        (Command::JOIN, MAX_TARGETS),
+        (Command::PART, MAX_TARGETS),
+        (Command::NAMES, MAX_TARGETS),
+        (Command::KICK, MAX_TARGETS),
+        (Command::PRIVMSG, 1),
+        (Command::NOTICE, 1),
+    ]
+}
+
 handle!{
     PRIVMSG with self::privmsg::Handler,
     NOTICE with self::privmsg::Handler,
     JOIN with self::join::Handler,
     INVITE with self::invite::Handler,
     WHO with self::who::Handler,
+    WHOIS with self::whois::Handler,
+    WHOWAS with self::whowas::Handler,
+    AWAY with self::away::Handler,
     MODE with self::mode::Handler,
     TOPIC with self::topic::Handler,
     NAMES with self::names::Handler,
+    LIST with self::list::Handler,
     PART with self::part::Handler,
+    KICK with self::kick::Handler,
     QUIT with self::quit::Handler,
     CAP with self::cap::Handler,
+    AUTHENTICATE with self::authenticate::Handler,
     NICK with self::nick::Handler,
     USER with self::user::Handler,
+    PING with self::ping::Handler,
+    PONG with self::ping::Handler,
+    MOTD with self::server_query::Handler,
+    LUSERS with self::server_query::Handler,
+    VERSION with self::server_query::Handler,
+    TIME with self::server_query::Handler,
+    INFO with self::server_query::Handler,
+    STATS with self::server_query::Handler,
+    OPER with self::oper::Handler,
+    KILL with self::kill::Handler,
+    KLINE with self::ban::Handler,
+    UNKLINE with self::ban::Handler,
+    DLINE with self::ban::Handler,
+    UNDLINE with self::ban::Handler,
+    WALLOPS with self::wallops::Handler,
+    DIE with self::server_control::Handler,
+    RESTART with self::server_control::Handler,
+    REHASH with self::server_control::Handler,
 }
\ No newline at end of file
@@ -10,12 +10,14 @@ use channel::Channel;
 use channel;
 use misc::Receiver;
 use misc;
+use casemapping;
 
 use super::{MessageHandler, ErrorMessage};
 
 /// Handler for MODE message
 ///
 /// `MODE <channel> {[+|-]|o|p|s|i|t|n|b|v} [<limit>] [<user>] [<ban mask>]`
+/// `MODE <nickname> *( ( "+" / "-" ) *( "i" / "w" / "o" / "s" / "x" ) )`
 #[derive(Debug)]
 pub struct Handler {
     msg: Message,
@@ -53,7 +55,7 @@ impl MessageHandler for Handler {
     fn invoke(self, server: &mut Server, client: Client) {
         let msg = self.msg;
         match self.recv {
-            Receiver::Channel(ref name) => match server.channels().get(name) {
+            Receiver::Channel(ref name) => match server.channel_with_name(name) {
                 Some(channel) => {
                     let _ = channel.with_ref_mut(move |channel| {
                         handle_mode(channel, client, msg)
@@ -64,11 +66,73 @@ impl MessageHandler for Handler {
                     &[&name, "No such channel"]
                 )
             },
-            Receiver::Nick(_) => error!("user modes not supported yet")
+            Receiver::Nick(ref nick) => handle_user_mode(server, client, nick, msg)
         }
     }
 }
 
+/// Handles the user mode message
+///
+/// Users can only query and change their own modes. `+o` is granted by
+/// OPER only and `+s` is reserved for operators.
+pub fn handle_user_mode(server: &Server, client: Client, nick: &str, message: Message) {
+    use user::UserMode::*;
+    if !casemapping::eq(nick, &*client.nick()) {
+        server.send_response(&client, ERR_USERSDONTMATCH, &["Cannot change mode for other users"]);
+        return
+    }
+    let mut params = message.params();
+    let _ = params.next();
+    let modes = match params.next() {
+        Some(modes) => String::from_utf8_lossy(modes).into_owned(),
+        None => {
+            let modes = client.info().modes();
+            server.send_response(&client, RPL_UMODEIS, &[&*modes]);
+            return
+        }
+    };
+    let mut changes = String::new();
+    let mut unknown = false;
+    {
+        let mut info = client.info_mut();
+        let mut set = true;
+        let mut last_action = None;
+        for c in modes.chars() {
+            let mode = match c {
+                '+' => { set = true; continue },
+                '-' => { set = false; continue },
+                c => match user::UserMode::from_char(c) {
+                    Some(mode) => mode,
+                    None => { unknown = true; continue }
+                }
+            };
+            let allowed = match mode {
+                Operator => !set,
+                ServerNotices => !set || info.is_operator(),
+                Invisible | Wallops | Cloaked => true
+            };
+            if allowed && info.set_mode(mode, set) {
+                if last_action != Some(set) {
+                    changes.push(if set { '+' } else { '-' });
+                    last_action = Some(set)
+                }
+                changes.push(mode.to_char());
+                // Operators lose the server notices together with their privileges
+                if mode == Operator && info.set_mode(ServerNotices, false) {
+                    changes.push(ServerNotices.to_char())
+                }
+            }
+        }
+    }
+    if unknown {
+        server.send_response(&client, ERR_UMODEUNKNOWNFLAG, &["Unknown MODE flag"]);
+    }
+    if !changes.is_empty() {
+        let nick = client.nick().to_string();
+        client.send_msg(MODE, &[&*nick, &*changes], MessageOrigin::User)
+    }
+}
+
     
 pub fn broadcast_change(channel: &channel::Channel, client: &Client, action: channel::Action,
                         flag: channel::ChannelMode, param: Option<&str>) {
@@ -142,7 +206,7 @@ pub fn handle_mode(channel: &mut channel::Channel, client: Client, message: Mess
             }
             match mode {
                 AnonChannel | InviteOnly | Moderated | MemberOnly 
-                | Quiet | Private | Secret | ReOpFlag | TopicProtect => {
+                | Quiet | Private | Secret | ReOpFlag | TopicProtect | SecureOnly => {
                     match action {
                         Add => {
                             channel.add_flag(mode);
@@ -251,4 +315,55 @@ pub fn handle_mode(channel: &mut channel::Channel, client: Client, message: Mess
             &[channel.name(), &("+".to_string() + &*channel.flags())]
         )
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn user_mode() {
+        test::run_server();
+        let mut user = test::Client::registered("umode_a");
+        let mut other = test::Client::registered("umode_b");
+        user.send_msg("MODE umode_a");
+        user.expect(":localhost 221 umode_a :+");
+        user.send_msg("MODE umode_b +i");
+        user.expect(":localhost 502 umode_a :Cannot change mode for other users");
+        user.send_msg("MODE umode_a +iwoq");
+        user.expect(":localhost 501 umode_a :Unknown MODE flag");
+        assert!(user.read_msg().ends_with(" MODE umode_a :+iw"));
+        user.send_msg("MODE umode_a -w+s");
+        assert!(user.read_msg().ends_with(" MODE umode_a :-w"));
+        user.send_msg("MODE umode_a");
+        user.expect(":localhost 221 umode_a :+i");
+        // Invisible users are hidden from non-members
+        user.send_msg("JOIN #umodetest");
+        user.skip_until(":localhost 366 umode_a");
+        other.send_msg("NAMES #umodetest");
+        other.expect_begin(":localhost 366 umode_b #umodetest ");
+        other.send_msg("WHO #umodetest");
+        other.expect_begin(":localhost 315 umode_b #umodetest ");
+    }
+
+    #[test]
+    fn host_cloaking() {
+        test::run_server();
+        let mut user = test::Client::registered("cloak_a");
+        let mut other = test::Client::registered("cloak_b");
+        user.send_msg("MODE cloak_a +x");
+        let msg = user.read_msg();
+        assert!(msg.ends_with(" MODE cloak_a :+x"));
+        // The mode message has already been sent with the cloak
+        let cloak = msg[":cloak_a!cloak_a@".len()..].split(' ').next().unwrap().to_string();
+        assert!(cloak.ends_with(".IP") || cloak.starts_with("test-"));
+        user.send_msg("PRIVMSG cloak_b :hello");
+        other.expect(&*format!(":cloak_a!cloak_a@{} PRIVMSG cloak_b :hello", cloak));
+        // The real host is only shown to the user itself
+        user.send_msg("WHOIS cloak_a");
+        user.expect(&*format!(":localhost 311 cloak_a cloak_a cloak_a {} * :Test user", cloak));
+        user.expect_begin(":localhost 378 cloak_a cloak_a :is connecting from *@");
+        user.skip_until(":localhost 318 cloak_a");
+        other.send_msg("WHOIS cloak_a");
+        other.expect(&*format!(":localhost 311 cloak_b cloak_a cloak_a {} * :Test user", cloak));
+        other.expect_begin(":localhost 312 cloak_b cloak_a ");
+    }
+}
@@ -28,7 +28,7 @@ impl MessageHandler for Handler {
     fn invoke(self, server: &mut Server, client: Client) {
         let mut i = 0;
         for chan_name in self.destinations.iter(self.msg.params()) {
-            if let Some(channel) = server.channels().get(chan_name) {
+            if let Some(channel) = server.channel_with_name(chan_name) {
                 let client = client.clone();
                 let _ = channel.with_ref(move |channel| channel.send_names(&client));
             }
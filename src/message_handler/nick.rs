@@ -1,12 +1,17 @@
 use std::str;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
 
 use protocol::{ResponseCode, Message};
 use protocol::ResponseCode::*;
-use client::Client;
+use protocol::Command::NICK;
+use client::{Client, ClientId, MessageOrigin};
+use client_io;
+use channel::Member;
 use server::Server;
 use user;
 use misc;
+use casemapping;
 
 use super::{MessageHandler, ErrorMessage};
 
@@ -31,6 +36,14 @@ impl MessageHandler for Handler {
                             "Erroneous nickname. Nickname has to be valid utf-8"
                         )
                     ))
+                } else if !misc::valid_nick(str::from_utf8(nick).unwrap()) {
+                    return Err((
+                        ERR_ERRONEUSNICKNAME,
+                        ErrorMessage::WithSubject(
+                            String::from_utf8_lossy(nick).into_owned(),
+                            "Erroneous nickname"
+                        )
+                    ))
                 } else if misc::is_reserved_nick(nick) {
                     return Err((
                         ERR_ERRONEUSNICKNAME,
@@ -51,40 +64,121 @@ impl MessageHandler for Handler {
     fn invoke(self, server: &mut Server, client: Client) {
         use user::Status::*;
         let nick = self.nick();
-        // Bypass borrow checker because of Rust issue #6393
-        let server_ptr = server as *mut Server;
-        // Note RFC issue #690, string has to be cloned twice now…
-        // TODO: handle renames delete old entries and convert to lower case first
-        match server.nicks_mut().entry(nick.to_string()) {
-            // Unsafe reborrow because of Rust issue #6393
-            Occupied(_) => unsafe {&*server_ptr}.send_response(
-                &client, ERR_NICKNAMEINUSE,
-                &[nick, "Nickname is already in use"]
-            ),
-            Vacant(entry) => {
-                entry.insert(client.id());
-                {let _ = client.info_mut().set_nick(nick.to_string());}
-                let status = {
-                    // Prevent dead-lock
-                    client.info().status()
-                };
-                match status {
-                    NameRegistered => {
-                        {client.info_mut().set_status(Registered)}
-                        // Unsafe reborrow because of Rust issue #6393
-                        unsafe {&*server_ptr}.register(&client)
-                    },
-                    Negotiating(&NameRegistered) => {
-                        client.info_mut().set_status(user::STATUS_NEG_REG)
-                    },
-                    Negotiating(_) => {
-                        client.info_mut().set_status(user::STATUS_NEG_NICKREG)
-                    }
-                    _ => {
-                        client.info_mut().set_status(NickRegistered)
-                    }
+        let key = casemapping::lower(nick);
+        let (old_nick, status) = {
+            // Prevent dead-lock
+            let info = client.info();
+            (info.nick().to_string(), info.status())
+        };
+        match server.nicks().get(&key) {
+            Some(&id) if id != client.id() => {
+                server.send_response(
+                    &client, ERR_NICKNAMEINUSE,
+                    &[nick, "Nickname is already in use"]
+                );
+                return
+            },
+            // Renaming to the current nick is a no-op,
+            // a change of the case is not
+            Some(_) if old_nick == nick => return,
+            _ => ()
+        }
+        let held = match server.nick_held_for(nick) {
+            Some(account) => !client.info().account().map_or(false, |name| casemapping::eq(name, account)),
+            None => false
+        };
+        if held {
+            server.send_response(
+                &client, ERR_UNAVAILRESOURCE,
+                &[nick, "Nick/channel is temporarily unavailable"]
+            );
+            return
+        }
+        let msg = set_nick(server, &client, nick);
+        match status {
+            Registered => {
+                broadcast_nick(server, client.clone(), msg);
+                server.nick_changed(&client)
+            },
+            NameRegistered => {
+                {client.info_mut().set_status(Registered)}
+                server.register(&client)
+            },
+            Negotiating(&NameRegistered) => {
+                client.info_mut().set_status(user::STATUS_NEG_REG)
+            },
+            Negotiating(_) => {
+                client.info_mut().set_status(user::STATUS_NEG_NICKREG)
+            }
+            _ => {
+                client.info_mut().set_status(NickRegistered)
+            }
+        }
+    }
+}
+
+/// Changes the nick of a registered client without any checks
+///
+/// Used by the services to take registered nicks from other users.
+pub fn force_nick(server: &mut Server, client: &Client, nick: &str) {
+    let msg = set_nick(server, client, nick);
+    broadcast_nick(server, client.clone(), msg)
+}
+
+/// Updates the nick of the client and returns the `NICK` message
+fn set_nick(server: &mut Server, client: &Client, nick: &str) -> Arc<Vec<u8>> {
+    let old_key = casemapping::lower(&*client.nick());
+    if server.nicks().get(&old_key) == Some(&client.id()) {
+        let _ = server.nicks_mut().remove(&old_key);
+    }
+    server.nicks_mut().insert(casemapping::lower(nick), client.id());
+    server.record_nick(client);
+    // Has to be built before the nick changes to get the old prefix
+    let msg = Arc::new(client.build_msg(NICK, &[nick], MessageOrigin::User));
+    {let _ = client.info_mut().set_nick(nick.to_string());}
+    msg
+}
+
+/// Renames the client in all of its channels and notifies every user
+/// sharing a channel exactly once
+fn broadcast_nick(server: &Server, client: Client, msg: Arc<Vec<u8>>) {
+    client.send(client_io::Event::SharedMessage(client.id(), msg.clone()));
+    let mut seen = HashSet::new();
+    seen.insert(client.id());
+    let recipients = Arc::new(Recipients {
+        msg: msg,
+        seen: Mutex::new(seen)
+    });
+    let (nick, mask) = {
+        let info = client.info();
+        (info.nick().to_string(), info.real_hostmask().clone())
+    };
+    // TODO make this faster
+    for proxy in server.channels().values() {
+        let recipients = recipients.clone();
+        let id = client.id();
+        let nick = nick.clone();
+        let mask = mask.clone();
+        let _ = proxy.with_ref_mut(move |channel| {
+            if channel.rename_member(&id, nick, mask) {
+                for member in channel.members() {
+                    recipients.send(member)
                 }
             }
+        });
+    }
+}
+
+/// Recipients of a nick change shared by all channels
+struct Recipients {
+    msg: Arc<Vec<u8>>,
+    seen: Mutex<HashSet<ClientId>>
+}
+
+impl Recipients {
+    fn send(&self, member: &Member) {
+        if self.seen.lock().unwrap().insert(member.id()) {
+            member.send(client_io::Event::SharedMessage(member.id(), self.msg.clone()))
         }
     }
 }
@@ -93,4 +187,60 @@ impl Handler {
     fn nick(&self) -> &str {
     	str::from_utf8(self.msg.params().next().unwrap()).unwrap()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn nick_change() {
+        test::run_server();
+        let mut a = test::Client::registered("nick_a");
+        let mut b = test::Client::registered("nick_b");
+        for chan in ["#nicktest1", "#nicktest2"].iter() {
+            a.send_msg(&format!("JOIN {}", chan));
+            a.skip_until(":localhost 366 nick_a");
+            b.send_msg(&format!("JOIN {}", chan));
+            b.skip_until(":localhost 366 nick_b");
+            a.expect_begin(":nick_b!");
+        }
+        a.send_msg("NICK nick_c");
+        assert!(a.read_msg().starts_with(":nick_a!"));
+        let msg = b.read_msg();
+        assert!(msg.starts_with(":nick_a!") && msg.ends_with(" NICK :nick_c"));
+        // Only a single NICK message is sent
+        b.send_msg("PING :sync");
+        b.expect(":localhost PONG localhost :sync");
+        b.send_msg("NAMES #NickTest2");
+        let mut names = Vec::new();
+        loop {
+            let msg = b.read_msg();
+            if msg.starts_with(":localhost 366 ") { break }
+            names.push(msg)
+        }
+        assert!(names.iter().any(|msg| msg.ends_with(" :@nick_c")));
+        b.send_msg("NICK NICK_C");
+        b.expect(":localhost 433 nick_b NICK_C :Nickname is already in use");
+        a.send_msg("NICK Nick_C");
+        a.expect_begin(":nick_c!");
+        assert!(b.read_msg().ends_with(" NICK :Nick_C"));
+        b.send_msg("PRIVMSG nick_c :hello");
+        assert!(a.read_msg().ends_with(" PRIVMSG nick_c :hello"));
+        b.send_msg("NICK nick_a");
+        a.expect_begin(":nick_b!");
+        b.expect_begin(":nick_b!");
+    }
+    #[test]
+    fn erroneous_nick() {
+        test::run_server();
+        let mut client = test::Client::new("nick_err".to_string());
+        // The test server allows 16 instead of the default 9 characters
+        client.send_msg("NICK nick_err_too_long");
+        client.skip_until(":localhost 432 * nick_err_too_long :Erroneous nickname");
+        client.send_msg("NICK 1nick");
+        client.skip_until(":localhost 432 * 1nick :Erroneous nickname");
+        client.send_msg("NICK nick_err_16chars");
+        client.send_msg("USER nick_err 0 * :Test user");
+        client.skip_until(":localhost 001 nick_err_16chars");
+    }
+}
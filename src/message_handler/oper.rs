@@ -0,0 +1,107 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::{OPER, MODE};
+use client::{Client, MessageOrigin};
+use server::Server;
+use user::UserMode;
+use services;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Handler for OPER message
+///
+/// `OPER <name> <password>`
+///
+/// The operator accounts are configured in the `[[operator]]` sections
+/// of the configuration file. Operators with a connection class are moved
+/// to it. Failed attempts are logged and count towards the login failures
+/// of the client.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        if message.params().count() < 2 {
+            Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", OPER), "Not enough parameters")
+            ))
+        } else {
+            Ok(Handler {
+                msg: message
+            })
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        let mut params = self.msg.params();
+        let name = String::from_utf8_lossy(params.next().unwrap());
+        let password = String::from_utf8_lossy(params.next().unwrap());
+        let mask = {
+            // Prevent dead-lock
+            client.info().real_hostmask().as_str().to_string()
+        };
+        match server.operator(&name) {
+            Some(op) if !op.host_allowed(&mask) => {
+                info!("failed OPER as {} by {}: host not allowed", name, mask);
+                server.send_response(&client, ERR_NOOPERHOST, &["No O-lines for your host"])
+            },
+            // Clients that failed too often are not checked anymore
+            Some(op) if services::may_log_in(&client) && op.check_password(&password) => {
+                {client.info_mut().set_mode(UserMode::Operator, true);}
+                if op.class.is_some() {
+                    client.traffic().set_max_sendq(server.class_sendq(op.class.as_ref().map(|v| &**v)));
+                }
+                info!("{} is now an IRC operator ({})", mask, name);
+                server.send_server_notice(&format!("{} is now an IRC operator ({})", mask, name));
+                server.send_response(&client, RPL_YOUREOPER, &["You are now an IRC operator"]);
+                let nick = client.nick().to_string();
+                client.send_msg(MODE, &[&*nick, "+o"], MessageOrigin::User)
+            },
+            Some(_) => {
+                info!("failed OPER as {} by {}: wrong password", name, mask);
+                server.send_response(&client, ERR_PASSWDMISMATCH, &["Password incorrect"]);
+                services::login_failed(server, &client)
+            },
+            None => {
+                info!("failed OPER as {} by {}: no such operator", name, mask);
+                server.send_response(&client, ERR_NOOPERHOST, &["No O-lines for your host"]);
+                services::login_failed(server, &client)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn oper() {
+        test::run_server();
+        let mut client = test::Client::registered("oper_test");
+        client.send_msg("OPER test");
+        client.expect(":localhost 461 oper_test OPER :Not enough parameters");
+        client.send_msg("OPER nobody secret");
+        client.expect(":localhost 491 oper_test :No O-lines for your host");
+        client.send_msg("OPER test wrong");
+        client.expect(":localhost 464 oper_test :Password incorrect");
+        client.send_msg("OPER test secret");
+        client.expect(":localhost 381 oper_test :You are now an IRC operator");
+        client.expect_begin(":oper_test!");
+    }
+
+    #[test]
+    /// Guessing operator passwords counts as failed logins
+    fn oper_failures() {
+        test::run_server();
+        let mut client = test::Client::registered("oper_guess");
+        for i in 0..4 {
+            client.send_msg(&*format!("OPER test guess{}", i));
+            client.expect(":localhost 464 oper_guess :Password incorrect");
+        }
+        client.send_msg("OPER nobody guess4");
+        client.expect(":localhost 491 oper_guess :No O-lines for your host");
+        client.expect("ERROR :Closing Link (Too many failed login attempts)");
+    }
+}
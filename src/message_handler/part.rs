@@ -41,7 +41,7 @@ impl MessageHandler for Handler {
     }
     fn invoke(self, server: &mut Server, client: Client) {
         for chan_name in self.channels.iter(self.msg.params()) {
-            if let Some(channel) = server.channels().get(chan_name) {
+            if let Some(channel) = server.channel_with_name(chan_name) {
                 let client = client.clone();
                 let reason = self.reason().map(|v| v.to_vec());
                 let _ = channel.with_ref_mut(move |channel| {
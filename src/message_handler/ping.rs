@@ -0,0 +1,55 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::{PING, PONG};
+use client::Client;
+use server::Server;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Handler for PING and PONG messages
+///
+/// `PING <server1> [ <server2> ]`
+/// `PONG <server> [ <server2> ]`
+///
+/// The liveness of a connection is tracked by the io worker which
+/// resets its idle timer on every inbound message. Thus a PONG does not
+/// need any further handling here.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        if message.params().next().is_some() {
+            Ok(Handler {
+                msg: message
+            })
+        } else {
+            Err((ERR_NOORIGIN, ErrorMessage::Plain("No origin specified")))
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if self.msg.command() == Some(PING) {
+            let host = client.server_name().clone();
+            server.send_raw_msg(&client, PONG, &[
+                host.as_bytes(),
+                self.msg.params().next().unwrap()
+            ])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn ping_pong() {
+        test::run_server();
+        let mut client = test::Client::registered("ping_test");
+        client.send_msg("PING :foo");
+        client.expect(":localhost PONG localhost :foo");
+        client.send_msg("PING");
+        client.expect_begin(":localhost 409 ping_test");
+    }
+}
@@ -48,7 +48,7 @@ impl MessageHandler for Handler {
     fn invoke(self, server: &mut Server, client: Client) {
         let msg = self.msg.params().nth(1);
         match self.recv {
-            Receiver::Channel(ref name) => match server.channels().get(name) {
+            Receiver::Channel(ref name) => match server.channel_with_name(name) {
                 Some(channel) => {
                     let msg = Arc::new(match msg {
                         Some(msg) => client.build_raw_msg(PRIVMSG, &[name.as_bytes(), msg], MessageOrigin::User),
@@ -110,7 +110,16 @@ impl MessageHandler for Handler {
                                 &[nick], 
                                 MessageOrigin::User
                             ),
-                        })
+                        });
+                        if !self.is_notice() {
+                            let away = {
+                                // Prevent dead-lock
+                                subject.info().away().map(|v| v.to_string())
+                            };
+                            if let Some(away) = away {
+                                client.send_response(RPL_AWAY, &[nick, &*away])
+                            }
+                        }
                     },
                     None => if ! self.is_notice() { client.send_response(
                         ERR_NOSUCHNICK,
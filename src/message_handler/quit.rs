@@ -1,8 +1,5 @@
-use std::sync::Arc;
-
-use protocol::{Command, ResponseCode, Message};
-use client::{Client, MessageOrigin};
-use client_io::Event;
+use protocol::{ResponseCode, Message};
+use client::Client;
 use server::Server;
 
 use super::{MessageHandler, ErrorMessage};
@@ -22,23 +19,7 @@ impl MessageHandler for Handler {
         })
     }
     fn invoke(self, server: &mut Server, client: Client) {
-        // Re-generate the message to ensure it is is well-formed
-        let msg = Arc::new(match self.reason() {
-            Some(reason) => client.build_raw_msg(Command::QUIT, &[reason], MessageOrigin::User),
-            None => client.build_msg(Command::QUIT, &[], MessageOrigin::User)
-        });
-        // TODO make this faster
-        for (_, proxy) in server.channels().iter() {
-            let msg = msg.clone();
-            let id = client.id();
-            let _ = proxy.with_ref_mut(move |channel| {
-                if let Some(_) = channel.member_with_id(id) {
-                    channel.broadcast_raw(msg);
-                    channel.remove_member(&id);
-                }
-            });
-        }
-        client.send(Event::Disconnect(client.id()))
+        server.quit_client(&client, self.reason())
     }
 }
 
@@ -0,0 +1,74 @@
+use protocol::{ResponseCode, Message, Command};
+use protocol::ResponseCode::*;
+use protocol::Command::{DIE, RESTART, REHASH, NOTICE};
+use client::Client;
+use server::Server;
+
+use super::{MessageHandler, ErrorMessage, is_operator};
+
+/// Handler for operator commands controlling the server
+///
+/// `DIE`
+/// `RESTART`
+/// `REHASH`
+///
+/// `RESTART` stops the server and executes it again with the same
+/// arguments. `REHASH` reloads the configuration file.
+#[derive(Debug)]
+pub struct Handler {
+    cmd: Command
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        Ok(Handler {
+            cmd: message.command().unwrap()
+        })
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if !is_operator(server, &client) {
+            return
+        }
+        let nick = client.nick().to_string();
+        match self.cmd {
+            DIE | RESTART => {
+                let restart = self.cmd == RESTART;
+                let notice = format!(
+                    "Server {} by {}", if restart { "restarting" } else { "terminating" }, nick
+                );
+                info!("{}", notice);
+                for recipient in server.clients() {
+                    let nick = recipient.nick().to_string();
+                    server.send_msg(recipient, NOTICE, &[&*nick, &*notice]);
+                }
+                server.shutdown(restart)
+            },
+            REHASH => match server.rehash() {
+                Ok(path) => server.send_response(&client, RPL_REHASHING, &[&*path, "Rehashing"]),
+                Err(err) => {
+                    error!("REHASH failed: {}", err);
+                    server.send_msg(&client, NOTICE, &[&*nick, &*format!("REHASH failed: {}", err)])
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn server_control() {
+        test::run_server();
+        let mut client = test::Client::registered("ctrl_test");
+        client.send_msg("DIE");
+        client.expect(":localhost 481 ctrl_test :Permission Denied- You're not an IRC operator");
+        client.send_msg("OPER test secret");
+        client.skip_until(":ctrl_test!");
+        client.send_msg("REHASH");
+        client.expect(
+            ":localhost NOTICE ctrl_test :REHASH failed: the server has been started without a configuration file"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use protocol::{ResponseCode, Message, Command};
+use protocol::ResponseCode::*;
+use protocol::Command::{MOTD, LUSERS, VERSION, TIME, INFO, STATS};
+use client::Client;
+use server::Server;
+
+use super::{MessageHandler, ErrorMessage};
+
+/// Handler for server queries
+///
+/// `MOTD [ <target> ]`
+/// `LUSERS [ <mask> [ <target> ] ]`
+/// `VERSION [ <target> ]`
+/// `TIME [ <target> ]`
+/// `INFO [ <target> ]`
+/// `STATS [ <query> [ <target> ] ]`
+///
+/// Since server links are not supported the only valid target
+/// is this server itself.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message,
+    cmd: Command
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let cmd = message.command().unwrap();
+        Ok(Handler {
+            msg: message,
+            cmd: cmd
+        })
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if let Some(target) = self.target() {
+            if target != server.host().as_bytes() {
+                server.send_response(&client, ERR_NOSUCHSERVER, &[
+                    &*String::from_utf8_lossy(target), "No such server"
+                ]);
+                return
+            }
+        }
+        match self.cmd {
+            MOTD => server.send_motd(&client),
+            LUSERS => server.send_lusers(&client),
+            VERSION => server.send_version(&client),
+            TIME => server.send_time(&client),
+            INFO => server.send_info(&client),
+            STATS => {
+                let query = self.msg.params().next()
+                    .and_then(|query| String::from_utf8_lossy(query).chars().next())
+                    .unwrap_or('*');
+                // Only the uptime is public
+                if query != 'u' && query != '*' && !super::is_operator(server, &client) {
+                    return
+                }
+                server.send_stats(&client, query)
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+impl Handler {
+    fn target(&self) -> Option<&[u8]> {
+        match self.cmd {
+            LUSERS | STATS => self.msg.params().nth(1),
+            _ => self.msg.params().nth(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn server_queries() {
+        test::run_server();
+        let mut client = test::Client::registered("query_test");
+        client.send_msg("MOTD");
+        client.expect(":localhost 422 query_test :MOTD File is missing");
+        client.send_msg("VERSION");
+        client.expect_begin(":localhost 351 query_test rauta-");
+        client.expect_begin(":localhost 005 query_test");
+        client.send_msg("TIME elsewhere");
+        client.expect(":localhost 402 query_test elsewhere :No such server");
+        client.send_msg("LUSERS");
+        client.expect_begin(":localhost 251 query_test :There are");
+        client.skip_until(":localhost 255 query_test");
+        client.send_msg("STATS u");
+        client.expect_begin(":localhost 242 query_test :Server Up 0 days ");
+        client.expect(":localhost 219 query_test u :End of STATS report");
+        client.send_msg("STATS l");
+        client.expect(":localhost 481 query_test :Permission Denied- You're not an IRC operator");
+        client.send_msg("OPER test secret");
+        client.skip_until(":localhost 381 query_test");
+        client.send_msg("STATS l");
+        client.skip_until(":localhost 211 query_test query_test[query_test!query_test@");
+        client.skip_until(":localhost 219 query_test l :End of STATS report");
+        client.send_msg("STATS y");
+        client.expect(":localhost 218 query_test Y default 120 0 :1048576");
+        client.expect(":localhost 218 query_test Y opers 120 0 :4194304");
+        client.expect(":localhost 219 query_test y :End of STATS report");
+    }
+}
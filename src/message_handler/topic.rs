@@ -50,7 +50,7 @@ impl MessageHandler for Handler {
     }
     fn invoke(self, server: &mut Server, client: Client) {
         let topic = self.topic().map(|v| v.to_vec());
-        match server.channels().get(self.name()) {
+        match server.channel_with_name(self.name()) {
             Some(channel) => {
                 let _ = channel.with_ref_mut(move |channel| {
                     let new_topic = match channel.member_with_id(client.id()) {
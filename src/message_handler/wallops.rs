@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use protocol::Command::WALLOPS;
+use client::{Client, MessageOrigin};
+use client_io::Event;
+use server::Server;
+use user::UserMode;
+
+use super::{MessageHandler, ErrorMessage, is_operator};
+
+/// Handler for WALLOPS message
+///
+/// `WALLOPS <text>`
+///
+/// The message is sent to all users with the user mode `+w`.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        if message.params().next().is_none() {
+            Err((
+                ERR_NEEDMOREPARAMS,
+                ErrorMessage::WithSubject(format!("{}", WALLOPS), "Not enough parameters")
+            ))
+        } else {
+            Ok(Handler {
+                msg: message
+            })
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if !is_operator(server, &client) {
+            return
+        }
+        let msg = Arc::new(client.build_raw_msg(
+            WALLOPS, &[self.msg.params().next().unwrap()], MessageOrigin::User
+        ));
+        for recipient in server.clients() {
+            if recipient.info().has_mode(UserMode::Wallops) {
+                recipient.send(Event::SharedMessage(recipient.id(), msg.clone()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn wallops() {
+        test::run_server();
+        let mut op = test::Client::registered("wallops_a");
+        op.send_msg("WALLOPS :hello");
+        op.expect(":localhost 481 wallops_a :Permission Denied- You're not an IRC operator");
+        op.send_msg("OPER test secret");
+        op.skip_until(":wallops_a!");
+        let mut user = test::Client::registered("wallops_b");
+        user.send_msg("MODE wallops_b +w");
+        user.expect_begin(":wallops_b!");
+        op.send_msg("WALLOPS :hello");
+        assert!(user.read_msg().ends_with(" WALLOPS :hello"));
+        // Operators without +w do not receive WALLOPS
+        op.send_msg("PING :sync");
+        op.expect(":localhost PONG localhost :sync");
+    }
+}
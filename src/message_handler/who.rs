@@ -23,7 +23,7 @@ use super::{MessageHandler, ErrorMessage};
 /// 
 /// Unfortunately the RFC 2812 does not specify what H, G, *, @ or + mean.
 /// @/+ is op/voice.
-/// * is maybe irc op
+/// * marks an IRC operator
 /// H/G means here/gone in terms of the away status
 /// WHO [<name> [<o>]]
 #[derive(Debug)]
@@ -44,7 +44,7 @@ impl MessageHandler for Handler {
         })
     }
     fn invoke(self, server: &mut Server, client: Client) {
-        match server.channels().get(&*String::from_utf8_lossy(self.mask())) {
+        match server.channel_with_name(&*String::from_utf8_lossy(self.mask())) {
             Some(channel) => {
                 let op_only = self.op_only;
                 let _ = channel.with_ref(move |channel| {
@@ -64,23 +64,31 @@ impl Handler {
 
 pub fn handle_who(channel: &Channel, client: Client, op_only: bool) {
     let sender = channel.list_sender(&client, RPL_WHOREPLY, RPL_ENDOFWHO);
-    if (channel.has_flag(Private) || channel.has_flag(Secret))
-    && !channel.member_with_id(client.id()).is_some() {
+    let is_member = channel.is_member(&client);
+    // Operators can see the real hosts
+    let is_operator = client.info().is_operator();
+    if (channel.has_flag(Private) || channel.has_flag(Secret)) && !is_member {
         // Don't give information about this channel to the outside
         // this should also be ok for secret because RPL_ENDOFWHO is
         // always sent.
         drop(sender);
     } else {
         for member in channel.members() {
-            if !op_only || member.is_op() {
+            // Invisible users are only visible to other members
+            if (!op_only || member.is_op()) && (is_member || !member.is_invisible()) {
+                let host = if is_operator {
+                    member.hostname().to_string()
+                } else {
+                    member.public_hostname()
+                };
                 sender.feed_items(&[
                     member.username(),
-                    member.hostname(),
+                    &*host,
                     member.client().server_name(),
                     member.nick(),
                     &*format!("{}{}{}", 
-                        "H", // always here as long away is not implemented
-                        "", // * is not supported yet
+                        if member.is_away() { "G" } else { "H" },
+                        if member.is_operator() { "*" } else { "" },
                         member.decoration()
                     ),
                     &*format!("0 {}", member.realname())
@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+use time;
+
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use client::Client;
+use server::{self, Server};
+use channel::Channel;
+use misc;
+
+use super::{MessageHandler, ErrorMessage, CommaSeparated};
+
+/// Handler for WHOIS message
+///
+/// `WHOIS [ <target> ] <mask> *( "," <mask> )`
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message,
+    nicks: CommaSeparated<str>,
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let index = if message.params().count() > 1 { 1 } else { 0 };
+        let nicks = CommaSeparated::verify_no_error(misc::verify_nick, message.params(), index);
+        if message.params().count() == 0 || nicks.iter(message.params()).count() == 0 {
+            Err((ERR_NONICKNAMEGIVEN, ErrorMessage::Plain("No nickname given")))
+        } else {
+            Ok(Handler {
+                msg: message,
+                nicks: nicks,
+            })
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if self.msg.params().count() > 1 {
+            // Only local clients exist, thus the target is either
+            // this server or the nick itself
+            let target = String::from_utf8_lossy(self.msg.params().next().unwrap());
+            if target != server.host() && server.client_with_name(&target).is_none() {
+                server.send_response(&client, ERR_NOSUCHSERVER, &[&*target, "No such server"]);
+                return
+            }
+        }
+        for nick in self.nicks.iter(self.msg.params()) {
+            let target = match server.client_with_name(nick) {
+                Some(target) => target.clone(),
+                None => {
+                    server.send_response(&client, ERR_NOSUCHNICK, &[nick, "No such nick/channel"]);
+                    server.send_response(&client, RPL_ENDOFWHOIS, &[nick, "End of WHOIS list"]);
+                    continue
+                }
+            };
+            {
+                // Prevent dead-lock if a client queries itself
+                let (nick, user, host, public_host, realname) = {
+                    let info = target.info();
+                    (info.nick().to_string(), info.user().to_string(), info.host().to_string(),
+                     info.public_host().to_string(), info.realname().to_string())
+                };
+                server.send_response(&client, RPL_WHOISUSER, &[
+                    &*nick, &*user, &*public_host, "*", &*realname
+                ]);
+                // Only operators and the user itself can see the real host
+                let is_operator = client.info().is_operator();
+                if is_operator || target.id() == client.id() {
+                    server.send_response(&client, RPL_WHOISHOST, &[
+                        &*nick, &*format!("is connecting from *@{}", host)
+                    ]);
+                }
+            }
+            let sender = Arc::new(WhoisSender {
+                client: client.clone(),
+                target: target,
+                host: server.host().to_string(),
+                channels: Mutex::new(Vec::new())
+            });
+            for proxy in server.channels().values() {
+                let sender = sender.clone();
+                let _ = proxy.with_ref(move |channel| sender.feed_channel(channel));
+            }
+            // The rest of the reply is sent when the last channel dropped the sender
+        }
+    }
+}
+
+/// Collects the channels of the WHOIS target
+///
+/// The sender is shared with the channels, the remaining replies are sent
+/// when the last reference is dropped.
+struct WhoisSender {
+    client: Client,
+    target: Client,
+    host: String,
+    channels: Mutex<Vec<String>>
+}
+
+impl WhoisSender {
+    fn feed_channel(&self, channel: &Channel) {
+        if let Some(member) = channel.member_with_id(self.target.id()) {
+            if channel.is_secret() && !channel.is_member(&self.client) {
+                return
+            }
+            self.channels.lock().unwrap().push(format!("{}{}", member.decoration(), channel.name()))
+        }
+    }
+}
+
+impl Drop for WhoisSender {
+    fn drop(&mut self) {
+        let (nick, away, secure, account, idle, signon) = {
+            // Prevent dead-lock if a client queries itself
+            let info = self.target.info();
+            (
+                info.nick().to_string(),
+                info.away().map(|v| v.to_string()),
+                info.is_secure(),
+                info.account().map(|v| v.to_string()),
+                time::get_time().sec - info.last_active(),
+                info.signon()
+            )
+        };
+        let nick = &*nick;
+        let channels = self.channels.lock().unwrap();
+        if channels.len() > 0 {
+            self.client.send_response(RPL_WHOISCHANNELS, &[nick, &*channels.join(" ")]);
+        }
+        self.client.send_response(RPL_WHOISSERVER, &[nick, &*self.host, server::DESCRIPTION]);
+        if let Some(away) = away {
+            self.client.send_response(RPL_AWAY, &[nick, &*away]);
+        }
+        if secure {
+            self.client.send_response(RPL_WHOISSECURE, &[nick, "is using a secure connection"]);
+        }
+        if let Some(account) = account {
+            self.client.send_response(RPL_WHOISACCOUNT, &[nick, &*account, "is logged in as"]);
+        }
+        self.client.send_response(RPL_WHOISIDLE, &[
+            nick,
+            &*idle.to_string(),
+            &*signon.to_string(),
+            "seconds idle, signon time"
+        ]);
+        self.client.send_response(RPL_ENDOFWHOIS, &[nick, "End of WHOIS list"]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn whois() {
+        test::run_server();
+        let mut target = test::Client::registered("whois_a");
+        target.send_msg("JOIN #whoistest");
+        target.skip_until(":localhost 366 whois_a");
+        let mut client = test::Client::registered("whois_b");
+        client.send_msg("WHOIS whois_a");
+        client.expect_begin(":localhost 311 whois_b whois_a whois_a ");
+        client.expect(":localhost 319 whois_b whois_a :@#whoistest");
+        client.expect(":localhost 312 whois_b whois_a localhost :An IRC server written in Rust");
+        client.expect_begin(":localhost 317 whois_b whois_a ");
+        client.expect(":localhost 318 whois_b whois_a :End of WHOIS list");
+        client.send_msg("WHOIS nobody");
+        client.expect(":localhost 401 whois_b nobody :No such nick/channel");
+        client.expect(":localhost 318 whois_b nobody :End of WHOIS list");
+    }
+}
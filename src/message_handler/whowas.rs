@@ -0,0 +1,87 @@
+use protocol::{ResponseCode, Message};
+use protocol::ResponseCode::*;
+use client::Client;
+use server::{self, Server};
+use misc;
+
+use super::{MessageHandler, ErrorMessage, CommaSeparated};
+
+/// Handler for WHOWAS message
+///
+/// `WHOWAS <nickname> *( "," <nickname> ) [ <count> [ <target> ] ]`
+///
+/// A count of zero or less returns all entries.
+#[derive(Debug)]
+pub struct Handler {
+    msg: Message,
+    nicks: CommaSeparated<str>,
+    count: usize,
+}
+
+impl MessageHandler for Handler {
+    fn from_message(message: Message) -> Result<Handler, (ResponseCode, ErrorMessage)> {
+        let nicks = CommaSeparated::verify_no_error(misc::verify_nick, message.params(), 0);
+        let count = message.params().nth(1)
+            .and_then(|v| String::from_utf8_lossy(v).parse::<isize>().ok())
+            .map_or(0, |v| if v > 0 { v as usize } else { 0 });
+        if message.params().count() == 0 || nicks.iter(message.params()).count() == 0 {
+            Err((ERR_NONICKNAMEGIVEN, ErrorMessage::Plain("No nickname given")))
+        } else {
+            Ok(Handler {
+                msg: message,
+                nicks: nicks,
+                count: count,
+            })
+        }
+    }
+    fn invoke(self, server: &mut Server, client: Client) {
+        if let Some(target) = self.msg.params().nth(2) {
+            if target != server.host().as_bytes() {
+                server.send_response(&client, ERR_NOSUCHSERVER, &[
+                    &*String::from_utf8_lossy(target), "No such server"
+                ]);
+                return
+            }
+        }
+        for nick in self.nicks.iter(self.msg.params()) {
+            let mut found = false;
+            for (i, entry) in server.history().lookup(nick).enumerate() {
+                if self.count > 0 && i == self.count {
+                    break
+                }
+                found = true;
+                server.send_response(&client, RPL_WHOWASUSER, &[
+                    &*entry.nick, &*entry.user, &*entry.host, "*", &*entry.realname
+                ]);
+                server.send_response(&client, RPL_WHOISSERVER, &[
+                    &*entry.nick, server.host(),
+                    &*server::format_time(&::time::at(::time::Timespec::new(entry.time, 0)))
+                ]);
+            }
+            if !found {
+                server.send_response(&client, ERR_WASNOSUCHNICK, &[nick, "There was no such nickname"]);
+            }
+            server.send_response(&client, RPL_ENDOFWHOWAS, &[nick, "End of WHOWAS"]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    fn whowas() {
+        test::run_server();
+        let mut old = test::Client::registered("whowas_a");
+        old.send_msg("NICK whowas_b");
+        old.expect_begin(":whowas_a!");
+        let mut client = test::Client::registered("whowas_c");
+        client.send_msg("WHOWAS whowas_a");
+        client.expect_begin(":localhost 314 whowas_c whowas_a whowas_a ");
+        client.expect_begin(":localhost 312 whowas_c whowas_a localhost :");
+        client.expect(":localhost 369 whowas_c whowas_a :End of WHOWAS");
+        client.send_msg("WHOWAS whowas_x");
+        client.expect(":localhost 406 whowas_c whowas_x :There was no such nickname");
+        client.expect(":localhost 369 whowas_c whowas_x :End of WHOWAS");
+    }
+}
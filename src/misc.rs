@@ -1,5 +1,40 @@
 //! Various helper functions
 use std::str;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use casemapping;
+
+/// Default maximum length of a nickname
+pub const NICK_LEN: usize = 9;
+/// Default maximum length of a channel name
+pub const CHANNEL_LEN: usize = 50;
+/// Characters a channel name may start with
+pub const CHANNEL_PREFIXES: &'static str = "#&+!";
+
+static MAX_NICK_LEN: AtomicUsize = ATOMIC_USIZE_INIT;
+static MAX_CHANNEL_LEN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Sets the configured name limits
+pub fn set_name_limits(nick_len: usize, channel_len: usize) {
+    MAX_NICK_LEN.store(nick_len, Ordering::Relaxed);
+    MAX_CHANNEL_LEN.store(channel_len, Ordering::Relaxed);
+}
+
+/// Maximum length of a nickname
+pub fn nick_len() -> usize {
+    match MAX_NICK_LEN.load(Ordering::Relaxed) {
+        0 => NICK_LEN,
+        len => len
+    }
+}
+
+/// Maximum length of a channel name
+pub fn channel_len() -> usize {
+    match MAX_CHANNEL_LEN.load(Ordering::Relaxed) {
+        0 => CHANNEL_LEN,
+        len => len
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum Receiver {
@@ -8,6 +43,11 @@ pub enum Receiver {
 }
 /// Checks if the nickname is valid
 pub fn valid_nick(nick: &str) -> bool {
+    valid_nick_len(nick, nick_len())
+}
+
+/// Checks if the nickname is valid and at most `max_len` characters long
+pub fn valid_nick_len(nick: &str, max_len: usize) -> bool {
     // <nick>       ::= <letter> { <letter> | <number> | <special> }
     //<special>    ::= '-' | '[' | ']' | '\' | '`' | '^' | '{' | '}'
     // 
@@ -15,7 +55,7 @@ pub fn valid_nick(nick: &str) -> bool {
     // nickname   =  ( letter / special ) *8( letter / digit / special / "-" )
     // special    =  %x5B-60 / %x7B-7D
     for (i, char) in nick.chars().enumerate() {
-        if i == 9 {
+        if i == max_len {
             return false
         }
         match char {
@@ -38,10 +78,13 @@ pub fn verify_nick<'a>(nick: &'a [u8]) -> Option<&'a str> {
 }
 
 pub fn valid_channel(channel: &str) -> bool {
+    let max_len = channel_len();
     for (i, char) in channel.chars().enumerate() {
+        if i == max_len {
+            return false
+        }
         match char {
-            '#' | '&' | '+' | '!' if i == 0 => {},
-            _ if i == 0 => { return false },
+            c if i == 0 => if !CHANNEL_PREFIXES.contains(c) { return false },
             ' ' | '\x07' | ',' => { return false }
             _ => {}
         }
@@ -73,27 +116,51 @@ pub fn verify_receiver<'a>(recv: &'a [u8]) -> Option<Receiver> {
     }
 }
 
+/// Checks if `name` matches the wildcard `mask`
+///
+/// `*` matches any sequence of characters.
+pub fn match_mask(mask: &str, name: &str) -> bool {
+    let mut mask_chars = mask.chars().peekable();
+    let mut chars = name.chars().peekable();
+    loop {
+        match mask_chars.next() {
+            Some('*') => match mask_chars.peek() {
+                // Consume all chars until next match is found
+                Some(next) => while match chars.peek() {
+                    Some(cha) => cha != next,
+                    None => false } { let _ = chars.next(); },
+                // * at end of the string matches the whole rest
+                None => return true
+            },
+            Some(mask_cha) => match chars.next() {
+                None => return false,
+                Some(cha) => if cha != mask_cha { return false }
+            },
+            None => break
+        }
+    }
+    !chars.next().is_some()
+}
+
 /// Checks if a nick is reserved
 pub fn is_reserved_nick(nick: &[u8]) -> bool {
-    // TODO convert to lover case first!
-    match nick {
-        b"*" => true,
-        b"NickServ" => true,
-        b"ChanServ" => true,
-        b"anonymous" => true,
-        _ => false
-    }
+    let nick = String::from_utf8_lossy(nick);
+    ["*", "NickServ", "ChanServ", "anonymous"].iter().any(
+        |reserved| casemapping::eq(reserved, &nick)
+    )
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{valid_nick, valid_channel};
+	use super::{valid_nick_len, valid_channel};
 	#[test]
 	/// Test the nickname validation function
 	fn test_nickname_validation() {
-		assert!(valid_nick("FooBar123"));
-		assert_eq!(valid_nick("FooBar1234"), false);
-		assert_eq!(valid_nick("1FooBar12"), false);
+		assert!(valid_nick_len("FooBar123", 9));
+		assert_eq!(valid_nick_len("FooBar1234", 9), false);
+		assert_eq!(valid_nick_len("1FooBar12", 9), false);
+		assert!(valid_nick_len("FooBar1234", 12));
+		assert_eq!(valid_nick_len("FooBar1234567", 12), false);
 	}
 	#[test]
 	/// Test the nickname validation function
@@ -102,5 +169,6 @@ mod tests {
 		assert_eq!(valid_channel("Foobar"), false);
 		assert_eq!(valid_channel("#Foo,bar"), false);
 		assert_eq!(valid_channel("Foo bar"), false);
+		assert_eq!(valid_channel(&*format!("#{:050}", 0)), false);
 	}
 }
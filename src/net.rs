@@ -4,7 +4,7 @@ extern crate libc;
 
 use self::libc::{sockaddr, sockaddr_in, sockaddr_in6, in_addr, in6_addr, c_int, c_char, socklen_t, AF_INET, AF_INET6};
 use std::mem::{size_of, transmute};
-use std::net;
+use std::net::{self, ToSocketAddrs};
 use std::ffi;
 
 /*
@@ -71,28 +71,30 @@ fn new_sockaddr_in6(port: u16, addr: in6_addr) -> sockaddr_in6 {
 //static NI_NUMERICSERV  : c_int = 0x00000008;
 //static NI_DGRAM    : c_int = 0x00000010;
 /// Returns the hostname for an ip address
-/// TODO: make this safe, see manpage
-const HOSTLEN: usize = 80;
-pub fn get_nameinfo(peer_socket: net::SocketAddr) -> String {
-    let port = peer_socket.port();
+///
+/// Blocks until the resolver answered. Returns `None` if the address
+/// has no name.
+const HOSTLEN: usize = 256;
+pub fn get_nameinfo(peer_socket: net::SocketAddr) -> Option<String> {
+    let port = peer_socket.port().to_be();
     let mut buf = [0; HOSTLEN];
-    let _ = unsafe {
+    let res = unsafe {
         match peer_socket {
             net::SocketAddr::V4(addr) => {
                 let [a, b, c, d] = addr.ip().octets();
+                // s_addr is stored in network byte order
                 let addr = in_addr {
-                    s_addr: (a as u32) << 24 
+                    s_addr: ((a as u32) << 24 
                           | (b as u32) << 16 
                           | (c as u32) << 8 
-                          | (d as u32)
+                          | (d as u32)).to_be()
                 };
                 let sockaddr = new_sockaddr_in(port, addr);
                 getnameinfo(transmute(&sockaddr), size_of::<sockaddr_in>() as socklen_t, 
                             buf.as_mut_ptr() as *mut i8, HOSTLEN as u32, transmute(0usize), 0, 0)
             },
             net::SocketAddr::V6(addr) => {
-                let [a, b, c, d, e, f, g, h] = addr.ip().segments();
-                let addr =  transmute([a, b, c, d, e, f, g, h]);
+                let addr = transmute(addr.ip().octets());
                 let sockaddr = new_sockaddr_in6(port, addr);
                 getnameinfo(transmute(&sockaddr), size_of::<sockaddr_in6>() as socklen_t, 
                             buf.as_mut_ptr() as *mut i8, HOSTLEN as u32, transmute(0usize), 0, 0)
@@ -100,7 +102,199 @@ pub fn get_nameinfo(peer_socket: net::SocketAddr) -> String {
         }
    
     };
-    unsafe {String::from_utf8_lossy(ffi::CStr::from_ptr(buf.as_ptr()).to_bytes()).into_owned()}
+    if res != 0 {
+        return None
+    }
+    let name = unsafe {String::from_utf8_lossy(ffi::CStr::from_ptr(buf.as_ptr()).to_bytes()).into_owned()};
+    // Without a name the numeric form of the address is returned
+    match name.parse::<net::IpAddr>() {
+        Ok(_) => None,
+        Err(_) => Some(name)
+    }
+}
 
+/// Result of a host name lookup
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum HostLookup {
+    /// The name resolves back to the address
+    Found(String),
+    /// The address has no (valid) name
+    NotFound,
+    /// The name does not resolve back to the address
+    Mismatch
+}
+
+/// Looks up the host name of an address
+///
+/// The name is only accepted if it resolves back to the same address.
+/// Blocks until the resolver answered.
+pub fn lookup_host_name(addr: net::IpAddr) -> HostLookup {
+    match get_nameinfo(net::SocketAddr::new(addr, 0)) {
+        Some(name) => confirm_host_name(addr, name),
+        None => HostLookup::NotFound
+    }
 }
 
+/// Accepts the name of an address if it resolves back to the address
+fn confirm_host_name(addr: net::IpAddr, name: String) -> HostLookup {
+    if !is_valid_host_name(&name) {
+        return HostLookup::NotFound
+    }
+    match (&*name, 0).to_socket_addrs() {
+        Ok(mut addrs) => if addrs.any(|v| v.ip() == addr) {
+            HostLookup::Found(name)
+        } else {
+            HostLookup::Mismatch
+        },
+        Err(_) => HostLookup::Mismatch
+    }
+}
+
+/// Textual form of an address that can be used in messages
+///
+/// IPv6 addresses starting with a colon would be parsed as the
+/// trailing parameter, thus a zero is prepended.
+pub fn host_from_ip(addr: net::IpAddr) -> String {
+    let host = addr.to_string();
+    if host.starts_with(':') {
+        format!("0{}", host)
+    } else {
+        host
+    }
+}
+
+/// Range of IP addresses in CIDR notation like `192.0.2.0/24`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: net::IpAddr,
+    prefix: u8
+}
+
+impl Cidr {
+    /// Creates the range of the `prefix` leading bits of the address
+    ///
+    /// The prefix is capped to the length of the address.
+    pub fn new(addr: net::IpAddr, prefix: u8) -> Cidr {
+        let prefix = match addr {
+            net::IpAddr::V4(_) => prefix.min(32),
+            net::IpAddr::V6(_) => prefix.min(128)
+        };
+        Cidr {
+            addr: mask_addr(addr, prefix),
+            prefix: prefix
+        }
+    }
+
+    /// Parses a range, a plain address is a range with a single address
+    pub fn parse(s: &str) -> Option<Cidr> {
+        let mut parts = s.splitn(2, '/');
+        let addr = match parts.next().and_then(|addr| addr.parse::<net::IpAddr>().ok()) {
+            Some(addr) => addr,
+            None => return None
+        };
+        let max = match addr {
+            net::IpAddr::V4(_) => 32,
+            net::IpAddr::V6(_) => 128
+        };
+        match parts.next().map(|prefix| prefix.parse::<u8>()) {
+            Some(Ok(prefix)) if prefix <= max => Some(Cidr::new(addr, prefix)),
+            Some(_) => None,
+            None => Some(Cidr::new(addr, max))
+        }
+    }
+
+    /// Checks whether the address is part of the range
+    pub fn contains(&self, addr: net::IpAddr) -> bool {
+        match (self.addr, addr) {
+            (net::IpAddr::V4(_), net::IpAddr::V4(_)) |
+            (net::IpAddr::V6(_), net::IpAddr::V6(_)) => mask_addr(addr, self.prefix) == self.addr,
+            _ => false
+        }
+    }
+}
+
+impl ::std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Clears all but the `prefix` leading bits of the address
+fn mask_addr(addr: net::IpAddr, prefix: u8) -> net::IpAddr {
+    match addr {
+        net::IpAddr::V4(addr) => {
+            let bits = u32::from(addr);
+            let mask = if prefix == 0 { 0 } else { !0u32 << (32 - prefix as u32) };
+            net::IpAddr::V4(net::Ipv4Addr::from(bits & mask))
+        },
+        net::IpAddr::V6(addr) => {
+            let mut segments = addr.segments();
+            for (i, segment) in segments.iter_mut().enumerate() {
+                let start = i as u32 * 16;
+                let prefix = prefix as u32;
+                if prefix <= start {
+                    *segment = 0
+                } else if prefix < start + 16 {
+                    *segment &= !0u16 << (16 - (prefix - start))
+                }
+            }
+            net::IpAddr::V6(net::Ipv6Addr::new(
+                segments[0], segments[1], segments[2], segments[3],
+                segments[4], segments[5], segments[6], segments[7]
+            ))
+        }
+    }
+}
+
+/// Checks whether a resolved name can be used as a host name
+fn is_valid_host_name(name: &str) -> bool {
+    name.len() <= 63 && name.len() > 0 && !name.starts_with('.') && !name.starts_with('-')
+        && name.chars().all(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' => true,
+            _ => false
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use super::{host_from_ip, is_valid_host_name, confirm_host_name, HostLookup, Cidr};
+
+    #[test]
+    fn host_names() {
+        assert_eq!(host_from_ip(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))), "127.0.0.1");
+        assert_eq!(host_from_ip(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1))), "0::1");
+        assert!(is_valid_host_name("host-1.example.com"));
+        assert!(!is_valid_host_name("host.example.com:1"));
+        assert!(!is_valid_host_name("-host"));
+        assert!(!is_valid_host_name(""));
+    }
+
+    #[test]
+    fn host_confirmation() {
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        assert_eq!(confirm_host_name(localhost, "localhost".to_string()), HostLookup::Found("localhost".to_string()));
+        // A name that does not resolve back to the address is ignored,
+        // the client keeps its IP address as host
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(confirm_host_name(other, "localhost".to_string()), HostLookup::Mismatch);
+        assert_eq!(confirm_host_name(other, "-invalid".to_string()), HostLookup::NotFound);
+    }
+
+    #[test]
+    fn cidr_ranges() {
+        let range = Cidr::parse("192.0.2.77/24").unwrap();
+        assert_eq!(range.to_string(), "192.0.2.0/24");
+        assert!(range.contains("192.0.2.1".parse().unwrap()));
+        assert!(!range.contains("192.0.3.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.1").unwrap().contains("10.0.0.1".parse().unwrap()));
+        assert!(!Cidr::parse("10.0.0.1").unwrap().contains("10.0.0.2".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains("10.0.0.2".parse().unwrap()));
+        let range = Cidr::parse("2001:db8::/33").unwrap();
+        assert!(range.contains("2001:db8:7fff::1".parse().unwrap()));
+        assert!(!range.contains("2001:db8:8000::1".parse().unwrap()));
+        assert_eq!(Cidr::parse("10.0.0.1/33"), None);
+        assert_eq!(Cidr::parse("example.com/8"), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Salted password hashes
+//!
+//! Hashes are stored as `pbkdf2-sha256$<iterations>$<salt>$<hash>` where
+//! salt and hash are hex encoded. A hash for the configuration file can be
+//! generated with `rauta --hash-password`, which reads the password from
+//! stdin.
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+
+const SCHEME: &'static str = "pbkdf2-sha256";
+const ITERATIONS: usize = 10000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Hashes a password with a random salt
+pub fn hash(password: &str) -> String {
+    let mut salt = [0; SALT_LEN];
+    rand_bytes(&mut salt).unwrap();
+    let hash = derive(password, &salt, ITERATIONS);
+    format!("{}${}${}${}", SCHEME, ITERATIONS, to_hex(&salt), to_hex(&hash))
+}
+
+/// Checks a password against a hash generated by `hash`
+pub fn verify(password: &str, hashed: &str) -> bool {
+    match parse(hashed) {
+        Some((iterations, salt, hash)) => {
+            memcmp::eq(&derive(password, &salt, iterations), &hash)
+        },
+        None => false
+    }
+}
+
+/// Checks whether the string is a hash generated by `hash`
+pub fn is_hash(hashed: &str) -> bool {
+    parse(hashed).is_some()
+}
+
+fn parse(hashed: &str) -> Option<(usize, Vec<u8>, Vec<u8>)> {
+    let mut parts = hashed.split('$');
+    match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(SCHEME), Some(iterations), Some(salt), Some(hash), None) => {
+            match (iterations.parse(), from_hex(salt), from_hex(hash)) {
+                (Ok(iterations), Some(salt), Some(hash)) => {
+                    if iterations > 0 && hash.len() == HASH_LEN {
+                        Some((iterations, salt, hash))
+                    } else {
+                        None
+                    }
+                },
+                _ => None
+            }
+        },
+        _ => None
+    }
+}
+
+fn derive(password: &str, salt: &[u8], iterations: usize) -> Vec<u8> {
+    let mut hash = vec![0; HASH_LEN];
+    pbkdf2_hmac(password.as_bytes(), salt, iterations, MessageDigest::sha256(), &mut hash).unwrap();
+    hash
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_digit(16)) {
+        return None
+    }
+    (0..hex.len() / 2).map(
+        |i| u8::from_str_radix(&hex[2*i..2*i + 2], 16).ok()
+    ).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, verify, is_hash};
+
+    #[test]
+    fn password_hash() {
+        let hashed = hash("secret");
+        assert!(is_hash(&hashed));
+        assert!(verify("secret", &hashed));
+        assert!(!verify("Secret", &hashed));
+        assert!(!verify("secret", "secret"));
+        assert!(hash("secret") != hashed);
+    }
+}
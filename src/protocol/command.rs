@@ -46,14 +46,37 @@ commands!{
     MODE        #[doc = "`MODE <channel> {[+|-]|o|p|s|i|t|n|b|v} [<limit>] [<user>] [<ban mask>]`"];
     JOIN        #[doc = "`JOIN ( <channel> *( \",\" <channel> ) [ <key> *( \",\" <key> ) ] )/ \"0\"`"];
 	INVITE		#[doc = "`INVITE <nickname> <channel>`"];
-    //PING        #[doc = "`PING` command"];
+    PING        #[doc = "`PING <server1> [ <server2> ]`"];
     WHO         #[doc = "`WHO [ <mask> [ \"o\" ] ]`"];
+    WHOIS       #[doc = "`WHOIS [ <target> ] <mask> *( \",\" <mask> )`"];
+    WHOWAS      #[doc = "`WHOWAS <nickname> *( \",\" <nickname> ) [ <count> [ <target> ] ]`"];
+    AWAY        #[doc = "`AWAY [ <text> ]`"];
     NAMES       #[doc = "`NAMES [ <channel> *( \",\" <channel> ) [ <target> ] ]`"];
+    LIST        #[doc = "`LIST [ <channel> *( \",\" <channel> ) [ <target> ] ]`"];
     TOPIC       #[doc = "`TOPIC <channel> [ <topic> ]`"];
     PART        #[doc = "`PART <channel> *( \",\" <channel> ) [ <Part Message> ]`"];
+    KICK        #[doc = "`KICK <channel> *( \",\" <channel> ) <user> *( \",\" <user> ) [<comment>]`"];
     QUIT        #[doc = "`QUIT [<reason>]`"];
-    //PONG        #[doc = "`PONG` command"];
+    PONG        #[doc = "`PONG <server> [ <server2> ]`"];
     NICK        #[doc = "`NICK <nickname> [ <hopcount> ]`"];
     USER        #[doc = "`USER <username> <hostname> <servername> <realname>`"];
     CAP         #[doc = "`CAP <subcommand> [ <param> ]`"];
+    AUTHENTICATE #[doc = "`AUTHENTICATE <mechanism> / <base64 data> / + / *`"];
+    ERROR       #[doc = "`ERROR <error message>`"];
+    MOTD        #[doc = "`MOTD [ <target> ]`"];
+    LUSERS      #[doc = "`LUSERS [ <mask> [ <target> ] ]`"];
+    VERSION     #[doc = "`VERSION [ <target> ]`"];
+    TIME        #[doc = "`TIME [ <target> ]`"];
+    INFO        #[doc = "`INFO [ <target> ]`"];
+    STATS       #[doc = "`STATS [ <query> [ <target> ] ]`"];
+    OPER        #[doc = "`OPER <name> <password>`"];
+    KILL        #[doc = "`KILL <nickname> <comment>`"];
+    KLINE       #[doc = "`KLINE [ <minutes> ] <user@host> [ <reason> ]`"];
+    UNKLINE     #[doc = "`UNKLINE <user@host>`"];
+    DLINE       #[doc = "`DLINE [ <minutes> ] <address range> [ <reason> ]`"];
+    UNDLINE     #[doc = "`UNDLINE <address range>`"];
+    WALLOPS     #[doc = "`WALLOPS <Text to be sent>`"];
+    DIE         #[doc = "`DIE`"];
+    RESTART     #[doc = "`RESTART`"];
+    REHASH      #[doc = "`REHASH`"];
 }
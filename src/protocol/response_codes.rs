@@ -10,8 +10,10 @@ pub enum ResponseCode {
     RPL_CREATED = 003,
     /// `<servername> <version> <available user modes> <available channel modes>`
     RPL_MYINFO = 004,
-    /// `Try server <server name>, port <port number>`
-    RPL_BOUNCE = 005,
+    /// `<token>{ <token>} :are supported by this server`
+    ///
+    /// Replaces `RPL_BOUNCE` (`Try server <server name>, port <port number>`) of RFC 2812.
+    RPL_ISUPPORT = 005,
     /// `:`
     RPL_USERHOST = 302,
     /// `:`
@@ -34,6 +36,12 @@ pub enum ResponseCode {
     RPL_ENDOFWHOIS = 318,
     /// `"<nick> :`
     RPL_WHOISCHANNELS = 319,
+    /// `<nick> :is using a secure connection`
+    RPL_WHOISSECURE = 671,
+    /// `<nick> <account> :is logged in as`
+    RPL_WHOISACCOUNT = 330,
+    /// `<nick> :is connecting from *@<host>`
+    RPL_WHOISHOST = 378,
     /// `<nick> <user> <host>`
     RPL_WHOWASUSER = 314,
     /// `<nick> :End of WHOWAS`
@@ -99,6 +107,8 @@ pub enum ResponseCode {
     RPL_YOURESERVICE = 383,
     /// `<server> :<string showing server's local time>`
     RPL_TIME = 391,
+    /// `<host> :is now your displayed host`
+    RPL_HOSTHIDDEN = 396,
     /// `:UserID   Terminal  Host`
     RPL_USERSSTART = 392,
     /// `:<username> <ttyline> <hostname>`
@@ -135,6 +145,12 @@ pub enum ResponseCode {
     RPL_STATSLINKINFO = 211,
     /// `<command> <count> <byte count> <remote count>`
     RPL_STATSCOMMANDS = 212,
+    /// `K <host> * <username> :<reason>`
+    RPL_STATSKLINE = 216,
+    /// `Y <class> <ping frequency> <connect frequency> <max sendq>`
+    RPL_STATSYLINE = 218,
+    /// `D <address range> :<reason>`
+    RPL_STATSDLINE = 225,
     /// `<stats letter> :End of STATS report`
     RPL_ENDOFSTATS = 219,
     /// `:Server Up %d days %d:%02d:%02d`
@@ -269,10 +285,30 @@ pub enum ResponseCode {
     ERR_RESTRICTED = 484,
     /// `:You're not the original channel operator`
     ERR_UNIQOPPRIVSNEEDED = 485,
+    /// `<channel> :Cannot join channel; SSL users only (+z)`
+    ERR_SECUREONLYCHAN = 489,
     /// `:No O-lines for your host`
     ERR_NOOPERHOST = 491,
     /// `:Unknown MODE flag`
     ERR_UMODEUNKNOWNFLAG = 501,
     /// `:Cannot change mode for other users`
     ERR_USERSDONTMATCH = 502,
+    /// `<nick>!<user>@<host> <account> :You are now logged in as <account>`
+    RPL_LOGGEDIN = 900,
+    /// `<nick>!<user>@<host> :You are now logged out`
+    RPL_LOGGEDOUT = 901,
+    /// `:You must use a nick assigned to you`
+    ERR_NICKLOCKED = 902,
+    /// `:SASL authentication successful`
+    RPL_SASLSUCCESS = 903,
+    /// `:SASL authentication failed`
+    ERR_SASLFAIL = 904,
+    /// `:SASL message too long`
+    ERR_SASLTOOLONG = 905,
+    /// `:SASL authentication aborted`
+    ERR_SASLABORTED = 906,
+    /// `:You have already authenticated using SASL`
+    ERR_SASLALREADY = 907,
+    /// `<mechanisms> :are available SASL mechanisms`
+    RPL_SASLMECHS = 908,
 }
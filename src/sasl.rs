@@ -0,0 +1,198 @@
+//! SASL authentication
+//!
+//! Clients that requested the `sasl` capability authenticate with
+//! `AUTHENTICATE` before the registration finishes. The client response is
+//! base64 encoded and sent in chunks of 400 bytes, a chunk of exactly 400
+//! bytes is followed by another chunk or by `+`.
+//!
+//! `PLAIN` checks the password of a NickServ account, `EXTERNAL` the
+//! fingerprint of the TLS client certificate.
+use openssl::hash::MessageDigest;
+use openssl::x509::X509Ref;
+
+/// Mechanisms that are advertised to the clients
+pub const MECHANISMS: &'static str = "PLAIN,EXTERNAL";
+/// Maximum length of a single chunk
+pub const CHUNK_LEN: usize = 400;
+/// Maximum length of the encoded client response
+const MAX_LEN: usize = 4 * CHUNK_LEN;
+
+/// SASL mechanism
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Account name and password
+    Plain,
+    /// TLS client certificate
+    External
+}
+
+impl Mechanism {
+    /// Parses the name of a mechanism, ignoring the case
+    pub fn from_name(name: &str) -> Option<Mechanism> {
+        match &*name.to_uppercase() {
+            "PLAIN" => Some(Mechanism::Plain),
+            "EXTERNAL" => Some(Mechanism::External),
+            _ => None
+        }
+    }
+
+    /// Name of the mechanism
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mechanism::Plain => "PLAIN",
+            Mechanism::External => "EXTERNAL"
+        }
+    }
+}
+
+/// Error while receiving the client response
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The response exceeds the length limit
+    TooLong,
+    /// The response is not valid base64
+    Invalid
+}
+
+/// Authentication in progress
+#[derive(Debug)]
+pub struct Session {
+    mechanism: Mechanism,
+    /// Encoded response received so far
+    buffer: String
+}
+
+impl Session {
+    pub fn new(mechanism: Mechanism) -> Session {
+        Session {
+            mechanism: mechanism,
+            buffer: String::new()
+        }
+    }
+
+    /// Getter for the mechanism
+    pub fn mechanism(&self) -> Mechanism {
+        self.mechanism
+    }
+
+    /// Adds a chunk of the client response
+    ///
+    /// Returns the decoded response once the last chunk has been received.
+    pub fn push(&mut self, chunk: &str) -> Result<Option<Vec<u8>>, Error> {
+        if chunk.len() > CHUNK_LEN || self.buffer.len() + chunk.len() > MAX_LEN {
+            return Err(Error::TooLong)
+        }
+        if chunk != "+" {
+            self.buffer.push_str(chunk)
+        }
+        if chunk.len() == CHUNK_LEN {
+            return Ok(None)
+        }
+        decode_base64(&self.buffer).map(Some).ok_or(Error::Invalid)
+    }
+}
+
+/// Splits a `PLAIN` response into the authorization identity, the
+/// authentication identity and the password
+pub fn parse_plain(response: &[u8]) -> Option<(String, String, String)> {
+    let fields: Vec<&[u8]> = response.split(|&b| b == 0).collect();
+    if fields.len() != 3 {
+        return None
+    }
+    match (String::from_utf8(fields[0].to_vec()),
+           String::from_utf8(fields[1].to_vec()),
+           String::from_utf8(fields[2].to_vec())) {
+        (Ok(authzid), Ok(authcid), Ok(password)) => Some((authzid, authcid, password)),
+        _ => None
+    }
+}
+
+/// Returns the hex encoded SHA-256 fingerprint of a certificate
+pub fn fingerprint(cert: &X509Ref) -> Option<String> {
+    cert.digest(MessageDigest::sha256()).ok().map(
+        |digest| digest.iter().map(|b| format!("{:02x}", b)).collect()
+    )
+}
+
+/// Checks whether a string is a hex encoded SHA-256 fingerprint
+pub fn is_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.len() == 64 && fingerprint.chars().all(|c| c.is_digit(16))
+}
+
+/// Decodes padded base64
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    let data = data.as_bytes();
+    if data.len() % 4 != 0 {
+        return None
+    }
+    let last = data.len() / 4;
+    let mut decoded = Vec::with_capacity(last * 3);
+    for (i, chunk) in data.chunks(4).enumerate() {
+        let mut bits = 0u32;
+        let mut padding = 0;
+        for (j, &c) in chunk.iter().enumerate() {
+            let value = match c {
+                b'A'...b'Z' => c - b'A',
+                b'a'...b'z' => c - b'a' + 26,
+                b'0'...b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' if i + 1 == last && j >= 2 => {
+                    padding += 1;
+                    0
+                },
+                _ => return None
+            };
+            if padding > 0 && c != b'=' {
+                return None
+            }
+            bits = bits << 6 | value as u32;
+        }
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        decoded.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Session, Mechanism, Error, CHUNK_LEN, decode_base64, parse_plain, is_fingerprint};
+
+    #[test]
+    fn base64_decoding() {
+        assert_eq!(decode_base64(""), Some(Vec::new()));
+        assert_eq!(decode_base64("cmF1dGE="), Some(b"rauta".to_vec()));
+        assert_eq!(decode_base64("cmF1dGEh"), Some(b"rauta!".to_vec()));
+        assert_eq!(decode_base64("cmF1dA=="), Some(b"raut".to_vec()));
+        assert_eq!(decode_base64("cmF1dGE"), None);
+        assert_eq!(decode_base64("cm=1dGEh"), None);
+        assert_eq!(decode_base64("cmF1dA=a"), None);
+        assert_eq!(decode_base64("cmF1dA==cmF1"), None);
+    }
+
+    #[test]
+    fn sasl_session() {
+        assert_eq!(Mechanism::from_name("plain"), Some(Mechanism::Plain));
+        assert_eq!(Mechanism::from_name("SCRAM-SHA-256"), None);
+        let mut session = Session::new(Mechanism::Plain);
+        assert_eq!(session.push("AGFsaWNlAHNlY3JldA=="), Ok(Some(b"\0alice\0secret".to_vec())));
+        assert_eq!(parse_plain(b"\0alice\0secret"), Some(("".to_string(), "alice".to_string(), "secret".to_string())));
+        assert_eq!(parse_plain(b"alice\0secret"), None);
+
+        let chunk: String = ::std::iter::repeat("QUFB").take(CHUNK_LEN / 4).collect();
+        let mut session = Session::new(Mechanism::External);
+        assert_eq!(session.push(&chunk), Ok(None));
+        assert_eq!(session.push("+"), Ok(Some(vec![b'A'; CHUNK_LEN / 4 * 3])));
+        let mut session = Session::new(Mechanism::External);
+        assert_eq!(session.push("+"), Ok(Some(Vec::new())));
+        let mut session = Session::new(Mechanism::External);
+        assert_eq!(session.push("@@@@"), Err(Error::Invalid));
+        let mut session = Session::new(Mechanism::Plain);
+        for _ in 0..4 {
+            assert_eq!(session.push(&chunk), Ok(None));
+        }
+        assert_eq!(session.push("QUFB"), Err(Error::TooLong));
+        assert!(is_fingerprint(&*::std::iter::repeat("a0").take(32).collect::<String>()));
+        assert!(!is_fingerprint("a0"));
+    }
+}
@@ -1,71 +1,142 @@
 //! Server model
 
 use std::io;
+use std::io::prelude::*;
 use std::net;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::thread::spawn;
+use std::time::Instant;
 use std::collections::HashMap;
+use std::default::Default;
 
 use mio::{self, EventLoop, Handler, Token};
+use net2::TcpBuilder;
+use openssl::ssl::SslAcceptor;
+use time;
 
 use protocol::{Command, ResponseCode, Message};
 use client::{ClientId, Client, MessageOrigin};
 use client_io;
 use message_handler;
-use channel;
-use services::{Service, NickServ, Action};
+use channel::{self, ChannelMode, ModeKind};
+use user::{self, Status, UserMode};
+use misc;
+use casemapping;
+use services::{Service, NickServ, Action, Credentials};
+use config::{self, Config, Listen, Operator, CloakSettings, Class};
+use history::History;
+use cloak::Cloak;
+use throttle::Throttle;
+use ban::BanList;
+
+/// Name and version of the server software
+pub const VERSION: &'static str = concat!("rauta-", env!("CARGO_PKG_VERSION"));
+/// Short description of the server
+pub const DESCRIPTION: &'static str = "An IRC server written in Rust";
+/// Milliseconds between the runs of the periodic tasks
+const TICK_INTERVAL: u64 = 1000;
 
 pub struct Server {
     host: String,
-    socket_addr: net::SocketAddr,
+    network: String,
+    listen: Vec<Listen>,
     clients: HashMap<ClientId, Client>,
     nicks: HashMap<String, ClientId>,
     channels: HashMap<String, channel::Proxy>,
-    listener: Option<mio::tcp::TcpListener>,
+    listeners: HashMap<Token, (mio::tcp::TcpListener, bool)>,
+    tls: Option<SslAcceptor>,
     server_tx: Option<mio::Sender<Event>>,
     client_tx: Option<mio::Sender<client_io::Event>>,
     services: HashMap<String, Rc<RefCell<Box<Service>>>>,
+    io_settings: client_io::Settings,
+    classes: Vec<Class>,
+    default_channel_modes: Vec<ChannelMode>,
+    created: String,
+    started: i64,
+    motd: Option<String>,
+    history: History,
+    operators: Vec<Operator>,
+    config_path: Option<String>,
+    restart: bool,
+    cloak: Option<Cloak>,
+    cloak_by_default: bool,
+    throttle: Throttle,
+    bans: BanList,
+    /// Nicks held for NickServ accounts and when the hold ends
+    held_nicks: HashMap<String, (String, i64)>,
 }
 
 pub enum Event {
     Connected(Client),
     Disconnected(Client),
-    InboundMessage(ClientId, Message)
+    InboundMessage(ClientId, Message),
+    /// A connection from the address has been closed
+    ConnectionClosed(net::IpAddr),
+    /// Stops the server
+    Shutdown
 }
 
 /// Irc server
 impl Server {
-    /// Creates a new IRC server instance.
-    pub fn new(host: &str) -> io::Result<Server> {
-        let addresses = try!(net::lookup_host(host));
-        // Listen only on ipv4 for now…
-        let addr = match addresses.filter_map(|v| v.ok()).filter_map(
-            |v| match v { 
-                net::SocketAddr::V4(addr) => {
-                    Some(net::SocketAddr::V4(net::SocketAddrV4::new(*addr.ip(), 6667)))
-                }
-                _ => None 
-        }).nth(0) {
-            Some(addr) => addr,
-            None => return Err(io::Error::new(
+    /// Creates a new IRC server instance from the configuration.
+    pub fn new(config: Config) -> io::Result<Server> {
+        if config.listen.is_empty() {
+            return Err(io::Error::new(
                 io::ErrorKind::Other,
-                "Cannot get host IP address."
+                "No listen address configured."
             ))
+        }
+        let tls = match config.tls {
+            Some(ref settings) => Some(try!(settings.acceptor().map_err(
+                |err| io::Error::new(io::ErrorKind::Other, err.to_string())
+            ))),
+            None => None
+        };
+        misc::set_name_limits(config.nick_len, config.channel_len);
+        casemapping::set(config.casemapping);
+        let bans = match config.ban_file {
+            Some(ref path) => try!(BanList::load(path).map_err(|err| io::Error::new(
+                err.kind(), format!("cannot load bans from {}: {}", path, err)
+            ))),
+            None => BanList::new()
         };
         let mut services = HashMap::new();
-        services.insert("NickServ".to_string(), Rc::new(RefCell::new(Box::new(NickServ::new()) as Box<Service>)));
+        if config.services.nickserv {
+            let storage = try!(config.services.storage().map_err(
+                |err| io::Error::new(io::ErrorKind::Other, err.to_string())
+            ));
+            let nickserv = NickServ::new(storage, config.services.enforce_delay);
+            services.insert(casemapping::lower("NickServ"), Rc::new(RefCell::new(Box::new(nickserv) as Box<Service>)));
+        }
         Ok(Server {
-            host: host.to_string(),
-            socket_addr: addr,
+            host: config.server_name,
+            network: config.network_name,
+            listen: config.listen,
             clients: HashMap::new(),
             nicks: HashMap::new(),
             channels: HashMap::new(),
-            listener: None,
+            listeners: HashMap::new(),
+            tls: tls,
             server_tx: None,
             client_tx: None,
             services: services,
+            io_settings: config.io,
+            classes: config.classes,
+            default_channel_modes: config.default_channel_modes,
+            created: format_time(&time::now()),
+            started: time::get_time().sec,
+            motd: config.motd,
+            history: History::new(config.whowas_len),
+            operators: config.operators,
+            config_path: config.path,
+            restart: false,
+            cloak: config.cloaking.as_ref().map(|settings| settings.cloak()),
+            cloak_by_default: config.cloaking.as_ref().map_or(false, |settings| settings.default),
+            throttle: Throttle::new(config.connections),
+            bans: bans,
+            held_nicks: HashMap::new(),
         })
     }
 
@@ -74,20 +145,30 @@ impl Server {
         let mut client_loop = try!(EventLoop::new());
         self.server_tx = Some(server_loop.channel());
         self.client_tx = Some(client_loop.channel());
-		// TODO listen to all IP addresses (move lookup_host to here)
-		self.listener = Some(try!(mio::tcp::TcpListener::bind(&self.socket_addr)));//&*format!("{}:{}", self.ip, self.port))));
-		info!("started listening on {} ({})", self.socket_addr, self.host);
-        try!(server_loop.register(
-            self.listener.as_ref().unwrap(),
-            Token(self.socket_addr.port() as usize),
-            mio::EventSet::readable(),
-            mio::PollOpt::edge()
-        ));
+        for (i, listen) in self.listen.iter().enumerate() {
+            let addr = &listen.address;
+            let listener = try!(bind_listener(addr).map_err(|err| io::Error::new(
+                err.kind(), format!("cannot listen on {}: {}", addr, err)
+            )));
+            info!("started listening on {} ({}{})", addr, self.host, if listen.tls { ", TLS" } else { "" });
+            try!(server_loop.register(
+                &listener,
+                Token(i),
+                mio::EventSet::readable(),
+                mio::PollOpt::edge()
+            ));
+            self.listeners.insert(Token(i), (listener, listen.tls));
+        }
+        if let Err(err) = server_loop.timeout_ms((), TICK_INTERVAL) {
+            error!("failed to schedule the server tick: {:?}", err)
+        }
         let host = Arc::new(self.host.clone());
         let tx = server_loop.channel();
+        let settings = self.io_settings.clone();
+        let tls = self.tls.clone();
         spawn(move || {
             use client_io::Worker;
-            let _ = client_loop.run(&mut Worker::new(tx, host)).unwrap();
+            let _ = client_loop.run(&mut Worker::new(tx, host, settings, tls)).unwrap();
         });
         server_loop.run(self)
     }
@@ -96,7 +177,7 @@ impl Server {
     /// This should only happen in the worker thread of the channel paniced.
     pub fn channel_lost(&mut self, name: &str) {
         // TODO propagate error
-        self.channels.remove(name);
+        self.channels.remove(&casemapping::lower(name));
     }
 
     /// Sends a response to the client
@@ -114,22 +195,355 @@ impl Server {
         client.send_raw_msg(cmd, payload, MessageOrigin::Server);
     }
 
-    pub fn register(&self, client: &Client) {
-        self.send_welcome_msg(client)
+    pub fn register(&mut self, client: &Client) {
+        if let Some((kind, reason)) = self.ban_reason(client) {
+            self.disconnect_banned(client, kind, &reason);
+            return
+        }
+        self.send_welcome_msg(client);
+        self.nick_changed(client)
+    }
+
+    /// Tells the services that a registered client took a new nick
+    pub fn nick_changed(&mut self, client: &Client) {
+        let services: Vec<_> = self.services.values().cloned().collect();
+        for service in services {
+            service.borrow_mut().nick_changed(self, client)
+        }
+    }
+
+    /// Checks SASL credentials with the services, returns the account name
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<String> {
+        let services: Vec<_> = self.services.values().cloned().collect();
+        services.iter().filter_map(|service| service.borrow_mut().authenticate(credentials)).next()
+    }
+
+    /// Checks whether SASL authentication is offered
+    pub fn sasl_available(&self) -> bool {
+        !self.services.is_empty()
+    }
+
+    /// Holds a nick for an account until `until`
+    ///
+    /// Only clients identified to the account can use the nick meanwhile.
+    pub fn hold_nick(&mut self, nick: &str, account: &str, until: i64) {
+        self.held_nicks.insert(casemapping::lower(nick), (account.to_string(), until));
+    }
+
+    /// Releases a held nick, returns whether it was held
+    pub fn release_nick(&mut self, nick: &str) -> bool {
+        let now = time::get_time().sec;
+        self.held_nicks.remove(&casemapping::lower(nick)).map_or(false, |(_, until)| until > now)
+    }
+
+    /// Returns the account a nick is held for
+    pub fn nick_held_for(&self, nick: &str) -> Option<&str> {
+        let now = time::get_time().sec;
+        match self.held_nicks.get(&casemapping::lower(nick)) {
+            Some(&(ref account, until)) if until > now => Some(account),
+            _ => None
+        }
+    }
+
+    /// Runs the periodic tasks of the server and the services
+    fn tick(&mut self) {
+        let now = time::get_time().sec;
+        let expired: Vec<String> = self.held_nicks.iter()
+            .filter(|&(_, &(_, until))| until <= now)
+            .map(|(nick, _)| nick.clone())
+            .collect();
+        for nick in expired {
+            self.held_nicks.remove(&nick);
+        }
+        let services: Vec<_> = self.services.values().cloned().collect();
+        for service in services {
+            service.borrow_mut().tick(self)
+        }
+    }
+
+    /// Getter for the K-lines and D-lines
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Getter for mut bans, `save_bans` has to be called after changing them
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    /// Writes the bans to the ban file
+    pub fn save_bans(&self) {
+        if let Err(err) = self.bans.save() {
+            error!("cannot save bans: {}", err)
+        }
+    }
+
+    /// Returns the kind and the reason of the ban that matches the client
+    ///
+    /// K-lines only apply to registered clients since the user name is
+    /// not known before.
+    fn ban_reason(&self, client: &Client) -> Option<(&'static str, String)> {
+        let now = time::get_time().sec;
+        let info = client.info();
+        if let Some(ban) = info.ip().and_then(|ip| self.bans.dline_for(ip, now)) {
+            return Some(("D-lined", ban.reason.clone()))
+        }
+        if info.status() == Status::Registered {
+            let ip = info.ip().map_or(String::new(), |ip| ip.to_string());
+            let hosts = [info.host(), &*ip];
+            if let Some(ban) = self.bans.kline_for(info.user(), &hosts, now) {
+                return Some(("K-lined", ban.reason.clone()))
+            }
+        }
+        None
+    }
+
+    /// Closes the connection of a banned client
+    fn disconnect_banned(&self, client: &Client, kind: &str, reason: &str) {
+        use protocol::ResponseCode::ERR_YOUREBANNEDCREEP;
+        self.send_response(client, ERR_YOUREBANNEDCREEP, &[
+            &*format!("You are banned from this server- {}", reason)
+        ]);
+        let reason = format!("{} ({})", kind, reason);
+        client.send_raw(format!("ERROR :Closing Link ({})\r\n", reason).into_bytes());
+        self.quit_client(client, Some(reason.as_bytes()))
+    }
+
+    /// Disconnects the clients that match a K-line or a D-line
+    pub fn enforce_bans(&self) {
+        for client in self.clients.values() {
+            if let Some((kind, reason)) = self.ban_reason(client) {
+                let mask = client.info().real_hostmask().as_str().to_string();
+                self.send_server_notice(&format!("{} active for {}", kind, mask));
+                self.disconnect_banned(client, kind, &reason)
+            }
+        }
     }
     
     /// Sends a welcome message to a newly registered client
     fn send_welcome_msg(&self, client: &Client) {
-        let info = client.info();
-        self.send_response(client, ResponseCode::RPL_WELCOME, &[&*format!(
-            "Welcome to the Internet Relay Network {nick}!{user}@{host}", 
-            nick=info.nick(),
-            user=info.user(),
-            host=info.host()
-        )])
+        use protocol::ResponseCode::*;
+        {
+            let info = client.info();
+            self.send_response(client, RPL_WELCOME, &[&*format!(
+                "Welcome to the Internet Relay Network {nick}!{user}@{host}", 
+                nick=info.nick(),
+                user=info.user(),
+                host=info.host()
+            )]);
+        }
+        self.send_response(client, RPL_YOURHOST, &[&*format!(
+            "Your host is {}, running version {}", self.host, VERSION
+        )]);
+        self.send_response(client, RPL_CREATED, &[&*format!(
+            "This server was created {}", self.created
+        )]);
+        let channel_modes: String = ChannelMode::all().iter().map(|&m| m as u8 as char).collect();
+        self.send_response(client, RPL_MYINFO, &[
+            &*self.host, VERSION, user::USER_MODES, &*channel_modes
+        ]);
+        self.send_isupport(client);
+        self.send_lusers(client);
+        self.send_motd(client);
+        let cloak = {
+            let info = client.info();
+            if info.has_mode(UserMode::Cloaked) {
+                info.cloaked_host().map(|host| host.to_string())
+            } else {
+                None
+            }
+        };
+        if let Some(host) = cloak {
+            self.send_response(client, RPL_HOSTHIDDEN, &[&*host, "is now your displayed host"]);
+        }
+    }
+
+    /// Returns the features supported by this server as `RPL_ISUPPORT` tokens
+    ///
+    /// The tokens are assembled from the limits the server enforces.
+    pub fn isupport(&self) -> Vec<String> {
+        let targets: Vec<String> = message_handler::target_limits().iter().map(
+            |&(ref cmd, limit)| format!("{}:{}", cmd, limit)
+        ).collect();
+        vec![
+            format!("CHANTYPES={}", misc::CHANNEL_PREFIXES),
+            format!("PREFIX=({}){}",
+                channel::PREFIXES.iter().map(|&(mode, _)| mode as u8 as char).collect::<String>(),
+                channel::PREFIXES.iter().map(|&(_, prefix)| prefix).collect::<String>()
+            ),
+            format!("CHANMODES={},{},{},{}",
+                channel::modes_of_kind(ModeKind::List),
+                channel::modes_of_kind(ModeKind::Parameter),
+                channel::modes_of_kind(ModeKind::SetParameter),
+                channel::modes_of_kind(ModeKind::Flag)
+            ),
+            format!("MODES={}", channel::MAX_MODES),
+            format!("NICKLEN={}", misc::nick_len()),
+            format!("CHANNELLEN={}", misc::channel_len()),
+            format!("AWAYLEN={}", user::AWAY_LEN),
+            format!("TARGMAX={}", targets.join(",")),
+            format!("ELIST={}", message_handler::ELIST),
+            format!("CASEMAPPING={}", casemapping::current().name()),
+            format!("NETWORK={}", self.network),
+        ]
     }
 
-    /// Getter for channels
+    /// Sends the `RPL_ISUPPORT` tokens to the client
+    pub fn send_isupport(&self, client: &Client) {
+        // Limit the number of tokens per line to keep the message short
+        for tokens in self.isupport().chunks(12) {
+            let mut payload: Vec<&str> = tokens.iter().map(|v| &**v).collect();
+            payload.push("are supported by this server");
+            self.send_response(client, ResponseCode::RPL_ISUPPORT, &*payload);
+        }
+    }
+
+    /// Sends the user statistics to the client
+    pub fn send_lusers(&self, client: &Client) {
+        use protocol::ResponseCode::*;
+        let users = self.clients.values().filter(
+            |c| c.info().status() == Status::Registered
+        ).count();
+        let unknown = self.clients.len() - users;
+        self.send_response(client, RPL_LUSERCLIENT, &[&*format!(
+            "There are {} users and {} services on 1 servers", users, self.services.len()
+        )]);
+        if unknown > 0 {
+            self.send_response(client, RPL_LUSERUNKNOWN, &[
+                &*unknown.to_string(), "unknown connection(s)"
+            ]);
+        }
+        self.send_response(client, RPL_LUSERCHANNELS, &[
+            &*self.channels.len().to_string(), "channels formed"
+        ]);
+        self.send_response(client, RPL_LUSERME, &[&*format!(
+            "I have {} clients and 0 servers", self.clients.len()
+        )]);
+    }
+
+    /// Sends the message of the day to the client
+    pub fn send_motd(&self, client: &Client) {
+        use protocol::ResponseCode::*;
+        match self.motd {
+            Some(ref motd) => {
+                self.send_response(client, RPL_MOTDSTART, &[&*format!(
+                    "- {} Message of the day - ", self.host
+                )]);
+                for line in motd.lines() {
+                    self.send_response(client, RPL_MOTD, &[&*format!("- {}", line)]);
+                }
+                self.send_response(client, RPL_ENDOFMOTD, &["End of MOTD command"]);
+            },
+            None => self.send_response(client, ERR_NOMOTD, &["MOTD File is missing"])
+        }
+    }
+
+    /// Sends the server version to the client
+    pub fn send_version(&self, client: &Client) {
+        self.send_response(client, ResponseCode::RPL_VERSION, &[
+            &*format!("{}.", VERSION), &*self.host, DESCRIPTION
+        ]);
+        self.send_isupport(client);
+    }
+
+    /// Sends the local time of the server to the client
+    pub fn send_time(&self, client: &Client) {
+        self.send_response(client, ResponseCode::RPL_TIME, &[
+            &*self.host, &*format_time(&time::now())
+        ]);
+    }
+
+    /// Sends information about the server to the client
+    pub fn send_info(&self, client: &Client) {
+        use protocol::ResponseCode::*;
+        for line in [
+            format!("{} - {}", VERSION, DESCRIPTION),
+            format!("Written by {}", env!("CARGO_PKG_AUTHORS")),
+            format!("Birth Date: {}", self.created),
+        ].iter() {
+            self.send_response(client, RPL_INFO, &[&line[..]]);
+        }
+        self.send_response(client, RPL_ENDOFINFO, &["End of INFO list"]);
+    }
+
+    /// Sends the statistics selected by `query` to the client
+    ///
+    /// - `d` the D-lines
+    /// - `k` the K-lines
+    /// - `l` the traffic of each connection
+    /// - `o` the operator accounts
+    /// - `u` the uptime of the server
+    /// - `y` the connection classes
+    pub fn send_stats(&self, client: &Client, query: char) {
+        use protocol::ResponseCode::*;
+        let now = time::get_time().sec;
+        match query {
+            'l' => for other in self.clients.values() {
+                let name = {
+                    let info = other.info();
+                    format!("{}[{}]", info.nick(), info.real_hostmask().as_str())
+                };
+                let traffic = other.traffic();
+                self.send_response(client, RPL_STATSLINKINFO, &[
+                    &*name,
+                    &*traffic.sendq().to_string(),
+                    &*traffic.sent_messages().to_string(),
+                    &*(traffic.sent_bytes() / 1024).to_string(),
+                    &*traffic.received_messages().to_string(),
+                    &*(traffic.received_bytes() / 1024).to_string(),
+                    &*(now - traffic.connected()).to_string()
+                ]);
+            },
+            'o' => for operator in self.operators.iter() {
+                for host in operator.hosts.iter() {
+                    self.send_response(client, RPL_STATSOLINE, &[
+                        "O", host.as_str(), "*", &*operator.name
+                    ]);
+                }
+            },
+            'y' => {
+                let ping = self.io_settings.ping_frequency.to_string();
+                self.send_response(client, RPL_STATSYLINE, &[
+                    "Y", config::DEFAULT_CLASS, &*ping, "0", &*self.io_settings.sendq.to_string()
+                ]);
+                for class in self.classes.iter() {
+                    self.send_response(client, RPL_STATSYLINE, &[
+                        "Y", &*class.name, &*ping, "0", &*class.sendq.to_string()
+                    ]);
+                }
+            },
+            'k' => for ban in self.bans.klines().iter().filter(|ban| !ban.is_expired(now)) {
+                let mut mask = ban.target.splitn(2, '@');
+                let user = mask.next().unwrap();
+                let host = mask.next().unwrap_or("*");
+                self.send_response(client, RPL_STATSKLINE, &["K", host, "*", user, &*ban.reason]);
+            },
+            'd' => for ban in self.bans.dlines().iter().filter(|ban| !ban.is_expired(now)) {
+                self.send_response(client, RPL_STATSDLINE, &["D", &*ban.target.to_string(), &*ban.reason]);
+            },
+            'u' => {
+                let up = now - self.started;
+                self.send_response(client, RPL_STATSUPTIME, &[&*format!(
+                    "Server Up {} days {}:{:02}:{:02}",
+                    up / 86400, up % 86400 / 3600, up % 3600 / 60, up % 60
+                )]);
+            },
+            _ => ()
+        }
+        self.send_response(client, RPL_ENDOFSTATS, &[&*query.to_string(), "End of STATS report"]);
+    }
+
+    /// Getter for the server name
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Gets a channel
+    pub fn channel_with_name(&self, name: &str) -> Option<&channel::Proxy> {
+        self.channels.get(&casemapping::lower(name))
+    }
+
+    /// Getter for channels, keyed by the lower case name
     pub fn channels(&self) ->  &HashMap<String, channel::Proxy> {
         &self.channels
     }
@@ -139,7 +553,7 @@ impl Server {
         &mut self.channels
     }
 
-    /// Getter for nicks
+    /// Getter for nicks, keyed by the lower case nick
     pub fn nicks(&self) ->  &HashMap<String, ClientId> {
         &self.nicks
     }
@@ -149,9 +563,14 @@ impl Server {
         &mut self.nicks
     }
 
+    /// Gets a client by its id
+    pub fn client_with_id(&self, id: ClientId) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
     /// Gets a client
     pub fn client_with_name(&self, name: &str) -> Option<&Client> {
-        match self.nicks.get(name) {
+        match self.nicks.get(&casemapping::lower(name)) {
             Some(id) => self.clients.get(id),
             None => None
         }
@@ -160,7 +579,7 @@ impl Server {
     /// Getter for services
     pub fn with_service<'a, F>(&'a mut self, name: &str, mut f: F) -> Action<'a>
     where F: FnMut(&mut Service, &'a mut Server) -> Action<'a> {
-        if let Some(service) = self.services.get(name).map(|v| v.clone()) {
+        if let Some(service) = self.services.get(&casemapping::lower(name)).map(|v| v.clone()) {
             debug!("calling service {}", name);
             f(&mut **service.borrow_mut(), self)
         } else {
@@ -168,6 +587,120 @@ impl Server {
         }
     }
 
+    /// Getter for the modes a new channel is created with
+    pub fn default_channel_modes(&self) -> &[ChannelMode] {
+        &self.default_channel_modes
+    }
+
+    /// Getter for the nickname history
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Records that the client gives up its current nickname
+    pub fn record_nick(&mut self, client: &Client) {
+        let info = client.info();
+        if info.status() == Status::Registered {
+            self.history.record(&*info)
+        }
+    }
+
+    /// Looks up an operator account
+    pub fn operator(&self, name: &str) -> Option<&Operator> {
+        self.operators.iter().find(|op| op.name == name)
+    }
+
+    /// Send queue limit of a connection class
+    ///
+    /// Connections without a class use the limit of the `[limits]` section.
+    pub fn class_sendq(&self, class: Option<&str>) -> usize {
+        class.and_then(|name| self.classes.iter().find(|class| class.name == name))
+            .map_or(self.io_settings.sendq, |class| class.sendq)
+    }
+
+    /// Iterates over all connected clients
+    pub fn clients<'a>(&'a self) -> Box<Iterator<Item=&'a Client> + 'a> {
+        Box::new(self.clients.values())
+    }
+
+    /// Calculates the cloaked host of the client
+    pub fn cloak_host(&self, client: &Client) {
+        if let Some(ref cloak) = self.cloak {
+            let mut info = client.info_mut();
+            let host = cloak.cloak(info.host());
+            info.set_cloaked_host(Some(host));
+            if self.cloak_by_default {
+                info.set_mode(UserMode::Cloaked, true);
+            }
+        }
+    }
+
+    /// Sends a server notice to all users with the user mode `+s`
+    pub fn send_server_notice(&self, text: &str) {
+        let notice = format!("*** Notice -- {}", text);
+        for client in self.clients.values() {
+            if client.info().has_mode(UserMode::ServerNotices) {
+                let nick = client.nick().to_string();
+                self.send_msg(client, Command::NOTICE, &[&*nick, &*notice]);
+            }
+        }
+    }
+
+    /// Removes the client from all channels, notifies the other
+    /// members and closes the connection
+    pub fn quit_client(&self, client: &Client, reason: Option<&[u8]>) {
+        // Re-generate the message to ensure it is is well-formed
+        let msg = Arc::new(match reason {
+            Some(reason) => client.build_raw_msg(Command::QUIT, &[reason], MessageOrigin::User),
+            None => client.build_msg(Command::QUIT, &[], MessageOrigin::User)
+        });
+        // TODO make this faster
+        for (_, proxy) in self.channels.iter() {
+            let msg = msg.clone();
+            let id = client.id();
+            let _ = proxy.with_ref_mut(move |channel| {
+                if let Some(_) = channel.member_with_id(id) {
+                    channel.broadcast_raw(msg);
+                    channel.remove_member(&id);
+                }
+            });
+        }
+        client.send(client_io::Event::Disconnect(client.id()))
+    }
+
+    /// Stops the server, `run_mio` returns once all pending events are handled
+    pub fn shutdown(&mut self, restart: bool) {
+        self.restart = restart;
+        let _ = self.tx().send(Event::Shutdown);
+    }
+
+    /// Checks whether the server should be restarted after it stopped
+    pub fn restart_requested(&self) -> bool {
+        self.restart
+    }
+
+    /// Reloads the configuration file
+    ///
+    /// Only the MOTD, the limits of names, the default channel modes,
+    /// the operators and the connection classes are applied, other changes
+    /// need a restart. New classes apply to new connections only.
+    /// Returns the path of the configuration file.
+    pub fn rehash(&mut self) -> Result<String, String> {
+        let path = match self.config_path {
+            Some(ref path) => path.clone(),
+            None => return Err("the server has been started without a configuration file".to_string())
+        };
+        let config = try!(Config::load(&path).map_err(|err| err.to_string()));
+        misc::set_name_limits(config.nick_len, config.channel_len);
+        self.motd = config.motd;
+        self.default_channel_modes = config.default_channel_modes;
+        self.operators = config.operators;
+        self.classes = config.classes;
+        self.throttle.set_settings(config.connections);
+        info!("reloaded configuration from {}", path);
+        Ok(path)
+    }
+
     /// Getter for tx for sending to main event loop
     /// Panics if the main loop is not started
     pub fn tx(&mut self) ->  &mio::Sender<Event> {
@@ -179,45 +712,190 @@ impl Handler for Server {
     type Timeout = ();
     type Message = Event;
 
-    fn notify(&mut self, _: &mut EventLoop<Server>, msg: Event) {
+    fn timeout(&mut self, event_loop: &mut EventLoop<Server>, _: ()) {
+        self.tick();
+        if let Err(err) = event_loop.timeout_ms((), TICK_INTERVAL) {
+            error!("failed to schedule the server tick: {:?}", err)
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut EventLoop<Server>, msg: Event) {
         use self::Event::*;
         match msg {
             InboundMessage(id, msg) => {
                 if let Some(client) = self.clients.get(&id).map(|c| c.clone()) {
+                    match msg.command() {
+                        Some(Command::PING) | Some(Command::PONG) => (),
+                        _ => client.info_mut().touch()
+                    }
                     message_handler::invoke(msg, self, client)
                 }
                 
             }
             Connected(client) => {
                 let id = client.id();
+                self.cloak_host(&client);
                 self.clients.insert(id, client);
             }
             Disconnected(client) => {
+                self.record_nick(&client);
                 self.clients.remove(&client.id());
-                self.nicks.remove(&*client.nick());
+                // The nick may have been released already and be in use again
+                let nick = casemapping::lower(&*client.nick());
+                if self.nicks.get(&nick) == Some(&client.id()) {
+                    self.nicks.remove(&nick);
+                }
+            }
+            ConnectionClosed(ip) => {
+                self.throttle.release(ip)
+            }
+            Shutdown => {
+                info!("shutting down");
+                if let Some(ref tx) = self.client_tx {
+                    let _ = tx.send(client_io::Event::Shutdown);
+                }
+                event_loop.shutdown()
             }
         }
     }
-    fn ready(&mut self, _: &mut EventLoop<Server>, _: Token, _: mio::EventSet) {
-        if let Ok(Some((stream, _))) = self.listener.as_ref().unwrap().accept() {
-            let _ = self.client_tx.as_ref().unwrap().send(client_io::Event::NewConnection(stream));
-        } 
+    fn ready(&mut self, _: &mut EventLoop<Server>, token: Token, _: mio::EventSet) {
+        let sendq = self.class_sendq(self.listen.get(token.0).and_then(|listen| listen.class.as_ref()).map(|v| &**v));
+        if let Some(&(ref listener, secure)) = self.listeners.get(&token) {
+            // Edge triggered, accept until the backlog is empty
+            loop {
+                match listener.accept() {
+                    Ok(Some((mut stream, addr))) => {
+                        if let Some(ban) = self.bans.dline_for(addr.ip(), time::get_time().sec) {
+                            debug!("refused connection from {}: D-lined", addr);
+                            let _ = write!(stream, "ERROR :Closing Link (D-lined ({}))\r\n", ban.reason);
+                            continue
+                        }
+                        if let Err(refusal) = self.throttle.check(addr.ip(), Instant::now()) {
+                            debug!("refused connection from {}: {}", addr, refusal.reason());
+                            // Best effort, the connection is closed anyway
+                            let _ = write!(stream, "ERROR :Closing Link ({})\r\n", refusal.reason());
+                            continue
+                        }
+                        let _ = self.client_tx.as_ref().unwrap().send(
+                            client_io::Event::NewConnection(stream, addr, secure, sendq)
+                        );
+                    },
+                    Ok(None) => break,
+                    Err(err) => {
+                        error!("accept on {:?} failed: {}", listener.local_addr(), err);
+                        break
+                    }
+                }
+            }
+        }
     }
 }
 
+/// Binds a listener to the address
+///
+/// IPv6 sockets are restricted to IPv6 such that they can be bound
+/// next to an IPv4 socket on the same port.
+fn bind_listener(addr: &net::SocketAddr) -> io::Result<mio::tcp::TcpListener> {
+    let builder = try!(match *addr {
+        net::SocketAddr::V4(..) => TcpBuilder::new_v4(),
+        net::SocketAddr::V6(..) => {
+            let builder = try!(TcpBuilder::new_v6());
+            try!(builder.only_v6(true));
+            Ok(builder)
+        }
+    });
+    try!(builder.reuse_address(true));
+    try!(builder.bind(addr));
+    let listener = try!(builder.listen(1024));
+    mio::tcp::TcpListener::from_listener(listener, addr)
+}
+
+/// Formats a time stamp for display
+pub fn format_time(tm: &time::Tm) -> String {
+    time::strftime("%A %B %e %Y -- %H:%M:%S %z", tm).unwrap_or(String::new())
+}
+
 #[cfg(test)]
 pub fn get_test_server() -> Server {
-    let mut services = HashMap::new();
-    services.insert("NickServ".to_string(), Rc::new(RefCell::new(Box::new(NickServ::new()) as Box<Service>)));
-    Server {
-        host: "localhost".to_string(),
-        socket_addr: net::SocketAddr::V4(net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 6667)),
-        clients: HashMap::new(),
-        nicks: HashMap::new(),
-        channels: HashMap::new(),
-        listener: None,
-        server_tx: None,
-        client_tx: None,
-        services: services
+    let mut config = get_test_config();
+    config.listen.push(Listen {
+        address: ::test::TLS_ADDR.parse().unwrap(),
+        tls: true,
+        class: None
+    });
+    config.tls = Some(::test::tls_settings());
+    Server::new(config).unwrap()
+}
+
+/// Configuration of the test server without the TLS listener
+#[cfg(test)]
+pub fn get_test_config() -> Config {
+    use password;
+    let mut config: Config = Default::default();
+    config.operators.push(Operator {
+        name: "test".to_string(),
+        password: password::hash("secret"),
+        hosts: vec![user::HostMask::new("*!*@*".to_string())],
+        class: Some("opers".to_string())
+    });
+    config.classes.push(Class {
+        name: "opers".to_string(),
+        sendq: 4 * config.io.sendq
+    });
+    config.cloaking = Some(CloakSettings {
+        key: "test key for the cloaks".to_string(),
+        prefix: "test".to_string(),
+        default: false
+    });
+    // Only the connections from other loopback addresses are limited
+    config.connections.per_ip = 3;
+    config.connections.exempt = vec![::net::Cidr::parse("127.0.0.1/32").unwrap()];
+    config.services.enforce_delay = 3;
+    // Longer than the default to check that the limit is configurable
+    config.nick_len = 16;
+    config
+}
+
+#[cfg(test)]
+mod test {
+    use test;
+    #[test]
+    /// The registration burst advertises the server limits
+    fn isupport() {
+        test::run_server();
+        let mut client = test::Client::new("isupport".to_string());
+        client.send_msg("NICK isupport");
+        client.send_msg("USER isupport 0 * :Test user");
+        client.skip_until(":localhost 004 isupport");
+        client.expect_begin(":localhost 005 isupport CHANTYPES=#&+! PREFIX=(ov)@+ CHANMODES=Ibe,k,l,aimnpqrstz MODES=6 NICKLEN=16 CHANNELLEN=50 AWAYLEN=160 TARGMAX=JOIN:10,PART:10,NAMES:10,KICK:10,PRIVMSG:1,NOTICE:1");
+    }
+    #[test]
+    /// Connections beyond the per address limit are refused
+    fn connection_limit() {
+        use std::thread::sleep;
+        use std::time::{Duration, Instant};
+        test::run_server();
+        let mut clients: Vec<test::Client> = (0..3).map(
+            |i| test::Client::from_addr(&*format!("limit{}", i), "127.0.0.2")
+        ).collect();
+        for client in clients.iter_mut() {
+            client.register()
+        }
+        let mut refused = test::Client::from_addr("limit3", "127.0.0.2");
+        refused.expect("ERROR :Closing Link (Too many connections from your host)");
+        clients.pop();
+        // The slot is freed once the server noticed the closed connection,
+        // refused connections do not count towards the throttle
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let mut client = test::Client::from_addr("limit4", "127.0.0.2");
+            let reply = client.read_msg();
+            if !reply.starts_with("ERROR ") {
+                client.register();
+                break
+            }
+            assert!(Instant::now() < deadline, "connection still refused: {}", reply);
+            sleep(Duration::from_millis(50));
+        }
     }
 }
\ No newline at end of file
@@ -17,12 +17,72 @@ use protocol::{Params, Message};
 use protocol::Command::{PRIVMSG};
 
 mod nickserv;
+mod storage;
 pub use self::nickserv::NickServ;
+pub use self::storage::{Account, Storage, FileStorage, PostgresStorage};
 
 /// Service error
 #[derive(Debug)]
 pub enum ServiceError {
-	DB // DB Error
+	/// Storage error
+	DB(String)
+}
+
+impl fmt::Display for ServiceError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ServiceError::DB(ref msg) => write!(f, "storage error: {}", msg)
+		}
+	}
+}
+
+impl Error for ServiceError {
+	fn description(&self) -> &str {
+		match *self {
+			ServiceError::DB(_) => "storage error"
+		}
+	}
+}
+
+/// Number of failed password checks after which a client is disconnected
+pub const MAX_LOGIN_FAILURES: u32 = 5;
+
+/// Checks whether the passwords given by the client should still be checked
+///
+/// Checking a password is expensive, clients that failed too often are
+/// refused without checking.
+pub fn may_log_in(client: &Client) -> bool {
+	client.info().login_failures() < MAX_LOGIN_FAILURES
+}
+
+/// Counts a failed password check and disconnects the client after
+/// `MAX_LOGIN_FAILURES` failures
+pub fn login_failed(server: &Server, client: &Client) {
+	let failures = client.info_mut().login_failed();
+	if failures == MAX_LOGIN_FAILURES {
+		let reason = "Too many failed login attempts";
+		info!("disconnecting {}: {}", client.info().real_hostmask().as_str(), reason);
+		client.send_raw(format!("ERROR :Closing Link ({})\r\n", reason).into_bytes());
+		server.quit_client(client, Some(reason.as_bytes()))
+	}
+}
+
+/// Sends a notice on behalf of the service to the client
+pub fn reply(server: &Server, service: &str, client: &Client, text: &str) {
+	let nick = client.nick().to_string();
+	client.send_raw(format!(
+		":{service}!{service}@{host} NOTICE {nick} :{text}\r\n",
+		service=service, host=server.host(), nick=nick, text=text
+	).into_bytes());
+}
+
+/// Credentials of a SASL authentication
+#[derive(Debug)]
+pub enum Credentials<'a> {
+	/// Account name and password, from `PLAIN`
+	Password(&'a str, &'a str),
+	/// Fingerprint of the TLS client certificate, from `EXTERNAL`
+	Certificate(&'a str)
 }
 
 /// Determines how to proceed after an service event handler has been processed
@@ -58,8 +118,18 @@ pub enum ArgType {
 pub use self::ArgType::*;
 
 impl ArgType {
-	fn check<'a>(&self, arg: &'a [u8]) -> Option<&'a str> {
-		str::from_utf8(arg).ok()
+	fn check<'a>(&self, arg: &'a str) -> Option<&'a str> {
+		match *self {
+			Text => Some(arg),
+			Email => {
+				let mut parts = arg.splitn(2, '@');
+				match (parts.next(), parts.next()) {
+					(Some(local), Some(domain)) if !local.is_empty() && !domain.contains('@')
+					&& domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') => Some(arg),
+					_ => None
+				}
+			}
+		}
 	}
 }
 
@@ -79,6 +149,7 @@ type ActionFn = for <'a> fn(&mut Any, &'a mut Server, &Client, args: HashMap<Str
 pub struct Command {
 	name: String,
 	args: Vec<Argument>,
+	syntax: Option<String>,
 	action: ActionFn,
 }
 
@@ -93,12 +164,13 @@ impl Command {
 		Command {
 			name: name.to_string(),
 			args: Vec::new(),
+			syntax: None,
 			action: action
 		}
 	}
 
 	/// Parses the command args and returns a HashMap containing at least the obligatory arguments
-	pub fn parse_args(&self, params: &mut Params) -> Result<HashMap<String, String>, ()> {
+	pub fn parse_args<'a, I: Iterator<Item=&'a str>>(&self, params: &mut I) -> Result<HashMap<String, String>, ()> {
 		let mut args = HashMap::new();
 		for arg in self.args.iter() {
 			let param = params.next();
@@ -129,29 +201,67 @@ impl Command {
 		self.args.push(Argument::new(name, arg_type));
 		self
 	}
+
+	/// Overrides the syntax of the arguments shown in the help
+	pub fn set_syntax(mut self, syntax: &str) -> Command {
+		self.syntax = Some(syntax.to_string());
+		self
+	}
+
+	/// Returns the syntax of the command like `REGISTER <password> <email>`
+	pub fn syntax(&self) -> String {
+		if let Some(ref syntax) = self.syntax {
+			return format!("{} {}", self.name, syntax)
+		}
+		let mut syntax = self.name.clone();
+		for arg in self.args.iter() {
+			match arg.arg_type {
+				Obligatory(_) => syntax.push_str(&format!(" <{}>", arg.name)),
+				Optional(_) => syntax.push_str(&format!(" [<{}>]", arg.name))
+			}
+		}
+		syntax
+	}
 }
 
 /// Trait for IRC services
 pub trait Service: BorrowMut<Any> {
+	fn name(&self) -> &str;
 	fn add_command(&mut self, Command);
 	fn commands(&self) -> &[Command];
 
+	/// Called when a registered client took a new nick
+	fn nick_changed(&mut self, _: &mut Server, _: &Client) {}
+	/// Called periodically by the server
+	fn tick(&mut self, _: &mut Server) {}
+	/// Checks SASL credentials, returns the name of the account
+	fn authenticate(&mut self, _: &Credentials) -> Option<String> {
+		None
+	}
+
 	fn process_message<'a>(&mut self, message: &Message, server: &'a mut Server, client: &Client) -> Action<'a> {
 		match message.command() {
 			Some(PRIVMSG) => {
-				let mut params = message.params();
-				let handler = if let Some(cmd) = params.nth(1).and_then(|s| self.find_command(s)) {
-					match cmd.parse_args(&mut params) {
-						Ok(args) => Some((cmd.action, args)),
-						Err(()) => None
-					}
-				} else {
-					None
+				// The text may be a single trailing parameter or split into several
+				let text: Vec<String> = message.params().skip(1).map(
+					|v| String::from_utf8_lossy(v).into_owned()
+				).collect();
+				let text = text.join(" ");
+				let mut params = text.split_whitespace();
+				let name = params.next().map(|v| v.to_string());
+				let handler = match name.as_ref().and_then(|name| self.find_command(name.as_bytes())) {
+					Some(cmd) => match cmd.parse_args(&mut params) {
+						Ok(args) => Ok((cmd.action, args)),
+						Err(()) => Err(format!("Insufficient parameters. Syntax: {}", cmd.syntax()))
+					},
+					None => Err(format!("Unknown command {}.", name.unwrap_or(String::new())))
 				};
-				if let Some((action, args)) = handler {
-					action(self.borrow_mut(), server, &client, args)
-				} else {
-					Action::Stop
+				match handler {
+					Ok((action, args)) => action(self.borrow_mut(), server, &client, args),
+					Err(text) => {
+						reply(server, self.name(), client, &text);
+						Action::Stop
+					}
 				}
 			}
 			_ => Action::Stop,
@@ -1,29 +1,136 @@
 //! NickServ service
 use std::borrow::{Borrow, BorrowMut};
-use std::str;
 use std::any::Any;
-use std::ascii::AsciiExt;
 use std::collections::HashMap;
 
-use client::{Client, MessageOrigin};
-use server::Server;
-use protocol::{Params, Message};
-use protocol::Command::{PRIVMSG};
+use rand;
+use time;
 
-use super::{Command, Service, ServiceError, Action};
-use super::{Obligatory, Text, Email};
+use client::{Client, ClientId};
+use server::{self, Server};
+use message_handler;
+use casemapping;
+use password;
+use sasl;
+
+use super::{Command, Service, ServiceError, Action, Account, Storage, Credentials};
+use super::{Obligatory, Optional, Text, Email};
+
+/// Name of the service
+const NAME: &'static str = "NickServ";
+
+/// Minimum length of an account password
+const PASSWORD_LEN: usize = 5;
+
+/// Seconds a nick is held after it has been taken from another user
+const HOLD_TIME: i64 = 60;
 
 pub struct NickServ {
-	commands: Vec<Command>
+	commands: Vec<Command>,
+	storage: Box<Storage>,
+	/// Seconds a client may use a registered nick before identifying
+	enforce_delay: i64,
+	/// Clients using a registered nick, the deadline and the nick
+	pending: HashMap<ClientId, (i64, String)>
 }
 
 impl Service for NickServ {
+	fn name(&self) -> &str {
+		NAME
+	}
 	fn add_command(&mut self, cmd: Command) {
 		self.commands.push(cmd);
 	}
 	fn commands<'a>(&'a self) -> &[Command] {
 		&*self.commands
 	}
+
+	/// Asks the client to identify if the nick is registered
+	fn nick_changed(&mut self, server: &mut Server, client: &Client) {
+		let _ = self.pending.remove(&client.id());
+		let nick = client.nick().to_string();
+		let account = match self.storage.find(&nick) {
+			Ok(Some(account)) => account,
+			Ok(None) => return,
+			Err(err) => {
+				error!("{}: {}", NAME, err);
+				return
+			}
+		};
+		if is_identified(client, &account) {
+			return
+		}
+		NickServ::reply(server, client, &format!(
+			"This nick is registered. Please identify via /msg {} IDENTIFY <password> \
+			 within {} seconds, otherwise your nick will be changed.", NAME, self.enforce_delay
+		));
+		self.pending.insert(client.id(), (time::get_time().sec + self.enforce_delay, nick));
+	}
+
+	/// Checks a password or a certificate fingerprint against the accounts
+	fn authenticate(&mut self, credentials: &Credentials) -> Option<String> {
+		let result = match *credentials {
+			Credentials::Password(name, password) => self.storage.find(name).map(
+				|account| account.and_then(|account| if password::verify(password, &account.password) {
+					Some(account)
+				} else {
+					None
+				})
+			),
+			Credentials::Certificate(certfp) => self.storage.find_by_certfp(certfp)
+		};
+		match result {
+			Ok(account) => account.map(|account| account.name),
+			Err(err) => {
+				error!("{}: {}", NAME, err);
+				None
+			}
+		}
+	}
+
+	/// Renames the clients that did not identify in time
+	fn tick(&mut self, server: &mut Server) {
+		let now = time::get_time().sec;
+		let expired: Vec<(ClientId, String)> = self.pending.iter()
+			.filter(|&(_, &(deadline, _))| deadline <= now)
+			.map(|(&id, &(_, ref nick))| (id, nick.clone()))
+			.collect();
+		for (id, nick) in expired {
+			let _ = self.pending.remove(&id);
+			let client = match server.client_with_id(id) {
+				Some(client) => client.clone(),
+				None => continue
+			};
+			if !casemapping::eq(&*client.nick(), &nick) {
+				continue
+			}
+			let account = match self.storage.find(&nick) {
+				Ok(Some(account)) => account,
+				_ => continue
+			};
+			if !is_identified(&client, &account) {
+				take_nick(server, &client, &account);
+			}
+		}
+	}
+}
+
+/// Checks whether the client is identified to the account
+fn is_identified(client: &Client, account: &Account) -> bool {
+	client.info().account().map_or(false, |name| casemapping::eq(name, &account.name))
+}
+
+/// Renames the client to a guest nick and holds its nick for the account
+fn take_nick(server: &mut Server, client: &Client, account: &Account) {
+	let nick = client.nick().to_string();
+	let mut guest = String::new();
+	while guest.is_empty() || server.client_with_name(&guest).is_some() {
+		guest = format!("Guest{:04}", rand::random::<u16>() % 10000);
+	}
+	info!("{}: renaming {} to {}", NAME, nick, guest);
+	NickServ::reply(server, client, &format!("Your nick has been changed to {}.", guest));
+	message_handler::force_nick(server, client, &guest);
+	server.hold_nick(&nick, &account.name, time::get_time().sec + HOLD_TIME)
 }
 
 impl BorrowMut<Any> for NickServ {
@@ -38,9 +145,16 @@ impl Borrow<Any> for NickServ {
 }
 
 impl NickServ {
-	pub fn new() -> NickServ {
+	/// Creates the service with the storage of the accounts
+	///
+	/// Clients using a registered nick are renamed after `enforce_delay`
+	/// seconds unless they identify.
+	pub fn new(storage: Box<Storage>, enforce_delay: u64) -> NickServ {
 		NickServ {
-			commands: Vec::new()
+			commands: Vec::new(),
+			storage: storage,
+			enforce_delay: enforce_delay as i64,
+			pending: HashMap::new()
 		}.init()
 	}
 	pub fn init(mut self) -> NickServ {
@@ -49,12 +163,478 @@ impl NickServ {
 				.add_arg("password", Obligatory(Text))
 				.add_arg("email", Obligatory(Email))
 		);
+		self.add_command(
+			Command::new("IDENTIFY", NickServ::identify)
+				.add_arg("account", Obligatory(Text))
+				.add_arg("password", Optional(Text))
+				.set_syntax("[<account>] <password>")
+		);
+		self.add_command(
+			Command::new("DROP", NickServ::drop)
+				.add_arg("nick", Optional(Text))
+		);
+		self.add_command(
+			Command::new("GROUP", NickServ::group)
+				.add_arg("target", Obligatory(Text))
+				.add_arg("password", Obligatory(Text))
+		);
+		self.add_command(
+			Command::new("INFO", NickServ::info)
+				.add_arg("nick", Optional(Text))
+		);
+		self.add_command(
+			Command::new("SET", NickServ::set)
+				.add_arg("option", Obligatory(Text))
+				.add_arg("value", Obligatory(Text))
+				.set_syntax("PASSWORD|EMAIL <value>")
+		);
+		self.add_command(
+			Command::new("CERT", NickServ::cert)
+				.add_arg("action", Obligatory(Text))
+				.add_arg("certfp", Optional(Text))
+				.set_syntax("ADD|DEL|LIST [<fingerprint>]")
+		);
+		self.add_command(
+			Command::new("GHOST", NickServ::ghost)
+				.add_arg("nick", Obligatory(Text))
+				.add_arg("password", Optional(Text))
+		);
+		self.add_command(
+			Command::new("RECOVER", NickServ::recover)
+				.add_arg("nick", Obligatory(Text))
+				.add_arg("password", Optional(Text))
+		);
+		self.add_command(
+			Command::new("RELEASE", NickServ::release)
+				.add_arg("nick", Obligatory(Text))
+				.add_arg("password", Optional(Text))
+		);
 		self
 	}
 
-	fn register<'a>(this: &mut Any, _: &'a mut Server, client: &Client, _: HashMap<String, String>) -> Action<'a> {
-		if let Some(_) = this.downcast_ref::<Self>() {
-			client.send_msg(PRIVMSG, &["cannot register new users at the moment"], MessageOrigin::Server)
+	fn downcast(this: &mut Any) -> &mut NickServ {
+		this.downcast_mut::<NickServ>().unwrap()
+	}
+
+	fn reply(server: &Server, client: &Client, text: &str) {
+		super::reply(server, NAME, client, text)
+	}
+
+	/// Logs the error and tells the client to try again
+	fn storage_error(server: &Server, client: &Client, err: ServiceError) {
+		error!("{}: {}", NAME, err);
+		NickServ::reply(server, client, "The account database is not available, please try again later.")
+	}
+
+	/// Looks up the account of a nick, replies if it is not registered
+	fn find_account(&mut self, server: &Server, client: &Client, nick: &str) -> Option<Account> {
+		match self.storage.find(nick) {
+			Ok(Some(account)) => Some(account),
+			Ok(None) => {
+				NickServ::reply(server, client, &format!("Nick {} isn't registered.", nick));
+				None
+			},
+			Err(err) => {
+				NickServ::storage_error(server, client, err);
+				None
+			}
+		}
+	}
+
+	/// Returns the account the client is identified to
+	fn identified_account(&mut self, server: &Server, client: &Client, cmd: &str) -> Option<Account> {
+		let name = client.info().account().map(|v| v.to_string());
+		match name {
+			Some(name) => self.find_account(server, client, &name),
+			None => {
+				NickServ::reply(server, client, &format!("You must be identified to use {}.", cmd));
+				None
+			}
+		}
+	}
+
+	/// Checks the password of the account, failures are logged and counted
+	fn verify_password(server: &Server, client: &Client, account: &Account, password: &str, cmd: &str) -> bool {
+		if !super::may_log_in(client) {
+			return false
+		}
+		if password::verify(password, &account.password) {
+			true
+		} else {
+			info!("failed {} for {} by {}", cmd, account.name, client.info().real_hostmask().as_str());
+			super::login_failed(server, client);
+			false
+		}
+	}
+
+	/// Returns the account of the nick if the client may act on behalf of it
+	///
+	/// The client has to be identified to the account or know its password.
+	fn authorize(&mut self, server: &Server, client: &Client, nick: &str, password: Option<&String>, cmd: &str) -> Option<Account> {
+		let account = match self.find_account(server, client, nick) {
+			Some(account) => account,
+			None => return None
+		};
+		if is_identified(client, &account) || password.map_or(false, |v| NickServ::verify_password(server, client, &account, v, cmd)) {
+			Some(account)
+		} else {
+			NickServ::reply(server, client, "Access denied.");
+			None
+		}
+	}
+
+	/// Returns the other client using the nick, replies if there is none
+	fn nick_user(server: &Server, client: &Client, nick: &str) -> Option<Client> {
+		match server.client_with_name(nick) {
+			Some(user) if user.id() == client.id() => {
+				NickServ::reply(server, client, "You cannot do that to yourself.");
+				None
+			},
+			Some(user) => Some(user.clone()),
+			None => {
+				NickServ::reply(server, client, &format!("Nick {} is not online.", nick));
+				None
+			}
+		}
+	}
+
+	/// Checks whether a password is good enough, replies if not
+	fn check_password(server: &Server, client: &Client, password: &str) -> bool {
+		if password.len() < PASSWORD_LEN || casemapping::eq(password, &*client.nick()) {
+			NickServ::reply(server, client, &format!(
+				"Please try again with a more obscure password. Passwords should be at least {} \
+				 characters long and should not be your nick.", PASSWORD_LEN
+			));
+			false
+		} else {
+			true
+		}
+	}
+
+	/// `REGISTER <password> <email>`
+	fn register<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = client.nick().to_string();
+		if !NickServ::check_password(server, client, &args["password"]) {
+			return Action::Stop
+		}
+		match this.storage.find(&nick) {
+			Ok(None) => (),
+			Ok(Some(_)) => {
+				NickServ::reply(server, client, &format!("Nick {} is already registered.", nick));
+				return Action::Stop
+			},
+			Err(err) => {
+				NickServ::storage_error(server, client, err);
+				return Action::Stop
+			}
+		}
+		let account = Account::new(&nick, password::hash(&args["password"]), &args["email"], time::get_time().sec);
+		match this.storage.create(&account) {
+			Ok(()) => {
+				info!("{} registered the account {}", client.info().real_hostmask().as_str(), nick);
+				client.info_mut().set_account(Some(nick.clone()));
+				NickServ::reply(server, client, &format!(
+					"Nick {} has been registered to you, you are now identified.", nick
+				))
+			},
+			Err(err) => NickServ::storage_error(server, client, err)
+		}
+		Action::Stop
+	}
+
+	/// `IDENTIFY [<account>] <password>`
+	fn identify<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let (name, password) = match args.get("password") {
+			Some(password) => (args["account"].clone(), password.clone()),
+			None => (client.nick().to_string(), args["account"].clone())
+		};
+		let account = match this.find_account(server, client, &name) {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		if NickServ::verify_password(server, client, &account, &password, "IDENTIFY") {
+			client.info_mut().set_account(Some(account.name.clone()));
+			NickServ::reply(server, client, &format!(
+				"Password accepted - you are now identified for {}.", account.name
+			))
+		} else {
+			NickServ::reply(server, client, &format!("Invalid password for {}.", name))
+		}
+		Action::Stop
+	}
+
+	/// `DROP [<nick>]`
+	///
+	/// Dropping the nick the account has been registered with drops the
+	/// whole account, other nicks are only removed from the account.
+	fn drop<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let mut account = match this.identified_account(server, client, "DROP") {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		let nick = args.get("nick").cloned().unwrap_or(client.nick().to_string());
+		if casemapping::eq(&nick, &account.name) {
+			if let Err(err) = this.storage.delete(&account.name) {
+				NickServ::storage_error(server, client, err);
+				return Action::Stop
+			}
+			info!("{} dropped the account {}", client.info().real_hostmask().as_str(), account.name);
+			for other in server.clients() {
+				let identified = other.info().account().map_or(false, |name| casemapping::eq(name, &account.name));
+				if identified {
+					other.info_mut().set_account(None)
+				}
+			}
+			NickServ::reply(server, client, &format!("Your account {} has been dropped.", account.name));
+			return Action::Stop
+		}
+		let len = account.nicks.len();
+		account.nicks.retain(|other| !casemapping::eq(other, &nick));
+		if account.nicks.len() == len {
+			NickServ::reply(server, client, &format!("Nick {} is not grouped to your account.", nick));
+			return Action::Stop
+		}
+		match this.storage.update(&account) {
+			Ok(()) => NickServ::reply(server, client, &format!(
+				"Nick {} has been removed from your account.", nick
+			)),
+			Err(err) => NickServ::storage_error(server, client, err)
+		}
+		Action::Stop
+	}
+
+	/// `GROUP <target> <password>`
+	///
+	/// Adds the current nick to the account of `target`.
+	fn group<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = client.nick().to_string();
+		match this.storage.find(&nick) {
+			Ok(None) => (),
+			Ok(Some(_)) => {
+				NickServ::reply(server, client, &format!("Nick {} is already registered.", nick));
+				return Action::Stop
+			},
+			Err(err) => {
+				NickServ::storage_error(server, client, err);
+				return Action::Stop
+			}
+		}
+		let mut account = match this.find_account(server, client, &args["target"]) {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		if !NickServ::verify_password(server, client, &account, &args["password"], "GROUP") {
+			NickServ::reply(server, client, &format!("Invalid password for {}.", args["target"]));
+			return Action::Stop
+		}
+		account.nicks.push(nick.clone());
+		match this.storage.update(&account) {
+			Ok(()) => {
+				client.info_mut().set_account(Some(account.name.clone()));
+				NickServ::reply(server, client, &format!("You are now in the group of {}.", account.name))
+			},
+			Err(err) => NickServ::storage_error(server, client, err)
+		}
+		Action::Stop
+	}
+
+	/// `INFO [<nick>]`
+	///
+	/// The email address and the grouped nicks are only shown to the owner
+	/// and to operators.
+	fn info<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = args.get("nick").cloned().unwrap_or(client.nick().to_string());
+		let account = match this.find_account(server, client, &nick) {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		let privileged = {
+			let info = client.info();
+			info.is_operator() || info.account().map_or(false, |name| casemapping::eq(name, &account.name))
+		};
+		let registered = server::format_time(&time::at(time::Timespec::new(account.registered, 0)));
+		NickServ::reply(server, client, &format!("{} is registered to the account {}", nick, account.name));
+		NickServ::reply(server, client, &format!("Registered: {}", registered));
+		if privileged {
+			NickServ::reply(server, client, &format!("Email: {}", account.email));
+			NickServ::reply(server, client, &format!("Nicks: {}", account.nicks.join(", ")));
+		}
+		Action::Stop
+	}
+
+	/// `CERT ADD [<fingerprint>]`, `CERT DEL <fingerprint>` and `CERT LIST`
+	///
+	/// Manages the TLS client certificates that log in to the account with
+	/// SASL EXTERNAL. `ADD` without a fingerprint adds the certificate of
+	/// the current connection.
+	fn cert<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let mut account = match this.identified_account(server, client, "CERT") {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		let certfp = match args.get("certfp") {
+			Some(certfp) => Some(certfp.to_lowercase()),
+			None => client.info().certfp().map(|v| v.to_string())
+		};
+		let action = args["action"].to_uppercase();
+		let done = match (&*action, certfp) {
+			("LIST", _) => {
+				NickServ::reply(server, client, &format!("Certificates of {}:", account.name));
+				for certfp in account.certfps.iter() {
+					NickServ::reply(server, client, certfp)
+				}
+				return Action::Stop
+			},
+			("ADD", None) | ("DEL", None) => {
+				NickServ::reply(server, client, "You are not using a client certificate.");
+				return Action::Stop
+			},
+			(_, Some(ref certfp)) if !sasl::is_fingerprint(certfp) => {
+				NickServ::reply(server, client, &format!("{} is not a valid SHA-256 fingerprint.", certfp));
+				return Action::Stop
+			},
+			("ADD", Some(certfp)) => {
+				match this.storage.find_by_certfp(&certfp) {
+					Ok(None) => (),
+					Ok(Some(_)) => {
+						NickServ::reply(server, client, &format!("Certificate {} is already in use.", certfp));
+						return Action::Stop
+					},
+					Err(err) => {
+						NickServ::storage_error(server, client, err);
+						return Action::Stop
+					}
+				}
+				account.certfps.push(certfp.clone());
+				format!("Certificate {} has been added to your account.", certfp)
+			},
+			("DEL", Some(certfp)) => {
+				let len = account.certfps.len();
+				account.certfps.retain(|other| *other != certfp);
+				if account.certfps.len() == len {
+					NickServ::reply(server, client, &format!("Certificate {} is not on your account.", certfp));
+					return Action::Stop
+				}
+				format!("Certificate {} has been removed from your account.", certfp)
+			},
+			_ => {
+				NickServ::reply(server, client, &format!("Unknown CERT action {}.", action));
+				return Action::Stop
+			}
+		};
+		match this.storage.update(&account) {
+			Ok(()) => NickServ::reply(server, client, &done),
+			Err(err) => NickServ::storage_error(server, client, err)
+		}
+		Action::Stop
+	}
+
+	/// `GHOST <nick> [<password>]`
+	///
+	/// Disconnects the user of a nick that belongs to the account.
+	fn ghost<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = &args["nick"];
+		let ghost = match NickServ::nick_user(server, client, nick) {
+			Some(ghost) => ghost,
+			None => return Action::Stop
+		};
+		if this.authorize(server, client, nick, args.get("password"), "GHOST").is_none() {
+			return Action::Stop
+		}
+		let reason = format!("GHOST command used by {}", &*client.nick());
+		info!("{}: {}", NAME, reason);
+		ghost.send_raw(format!("ERROR :Closing Link ({})\r\n", reason).into_bytes());
+		server.quit_client(&ghost, Some(reason.as_bytes()));
+		// Release the nick right away instead of once the connection is closed
+		server.nicks_mut().remove(&casemapping::lower(&*ghost.nick()));
+		NickServ::reply(server, client, &format!("Ghost with your nick {} has been killed.", nick));
+		Action::Stop
+	}
+
+	/// `RECOVER <nick> [<password>]`
+	///
+	/// Renames the user of a nick that belongs to the account and holds the
+	/// nick such that only clients identified to the account can use it.
+	fn recover<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = &args["nick"];
+		let user = match NickServ::nick_user(server, client, nick) {
+			Some(user) => user,
+			None => return Action::Stop
+		};
+		let account = match this.authorize(server, client, nick, args.get("password"), "RECOVER") {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		if !is_identified(client, &account) {
+			// Knowing the password is as good as identifying
+			client.info_mut().set_account(Some(account.name.clone()));
+		}
+		let _ = this.pending.remove(&user.id());
+		take_nick(server, &user, &account);
+		NickServ::reply(server, client, &format!(
+			"The user of {} has been renamed, the nick is held for you for {} seconds.", nick, HOLD_TIME
+		));
+		Action::Stop
+	}
+
+	/// `RELEASE <nick> [<password>]`
+	///
+	/// Releases a nick that is held after it has been taken from a user.
+	fn release<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let nick = &args["nick"];
+		if this.authorize(server, client, nick, args.get("password"), "RELEASE").is_none() {
+			return Action::Stop
+		}
+		if server.release_nick(nick) {
+			NickServ::reply(server, client, &format!("Nick {} has been released.", nick))
+		} else {
+			NickServ::reply(server, client, &format!("Nick {} is not being held.", nick))
+		}
+		Action::Stop
+	}
+
+	/// `SET PASSWORD <password>` and `SET EMAIL <email>`
+	fn set<'a>(this: &mut Any, server: &'a mut Server, client: &Client, args: HashMap<String, String>) -> Action<'a> {
+		let this = NickServ::downcast(this);
+		let mut account = match this.identified_account(server, client, "SET") {
+			Some(account) => account,
+			None => return Action::Stop
+		};
+		let value = &args["value"];
+		let option = args["option"].to_uppercase();
+		let done = match &*option {
+			"PASSWORD" => {
+				if !NickServ::check_password(server, client, value) {
+					return Action::Stop
+				}
+				account.password = password::hash(value);
+				"Your password has been changed."
+			},
+			"EMAIL" => {
+				if Email.check(value).is_none() {
+					NickServ::reply(server, client, &format!("{} is not a valid email address.", value));
+					return Action::Stop
+				}
+				account.email = value.to_string();
+				"Your email address has been changed."
+			},
+			_ => {
+				NickServ::reply(server, client, &format!("Unknown SET option {}.", option));
+				return Action::Stop
+			}
+		};
+		match this.storage.update(&account) {
+			Ok(()) => NickServ::reply(server, client, done),
+			Err(err) => NickServ::storage_error(server, client, err)
 		}
 		Action::Stop
 	}
@@ -69,6 +649,119 @@ mod test {
         test::run_server();
         let mut client = test::Client::registered("nickserv_test");
         client.send_msg("PRIVMSG NickServ REGISTER user email@email");
-        client.expect_begin(":localhost PRIVMSG :cannot register new users at the moment");
+        client.expect(":NickServ!NickServ@localhost NOTICE nickserv_test :Insufficient parameters. Syntax: REGISTER <password> <email>");
+        client.send_msg("PRIVMSG NickServ :FOO");
+        client.expect(":NickServ!NickServ@localhost NOTICE nickserv_test :Unknown command FOO.");
+    }
+
+    #[test]
+    fn accounts() {
+        test::run_server();
+        let mut owner = test::Client::registered("ns_owner");
+        let notice = ":NickServ!NickServ@localhost NOTICE ns_owner :";
+        owner.send_msg("PRIVMSG NickServ :REGISTER abc owner@example.com");
+        owner.expect(&*format!("{}Please try again with a more obscure password. \
+            Passwords should be at least 5 characters long and should not be your nick.", notice));
+        owner.send_msg("PRIVMSG NickServ :REGISTER secret owner@example.com");
+        owner.expect(&*format!("{}Nick ns_owner has been registered to you, you are now identified.", notice));
+        owner.send_msg("PRIVMSG NickServ :REGISTER secret owner@example.com");
+        owner.expect(&*format!("{}Nick ns_owner is already registered.", notice));
+        owner.send_msg("PRIVMSG NickServ :SET EMAIL owner@example.org");
+        owner.expect(&*format!("{}Your email address has been changed.", notice));
+        owner.send_msg("PRIVMSG NickServ :SET PASSWORD hunter2");
+        owner.expect(&*format!("{}Your password has been changed.", notice));
+
+        let mut other = test::Client::registered("ns_other");
+        let other_notice = ":NickServ!NickServ@localhost NOTICE ns_other :";
+        other.send_msg("PRIVMSG NickServ :IDENTIFY ns_owner secret");
+        other.expect(&*format!("{}Invalid password for ns_owner.", other_notice));
+        other.send_msg("PRIVMSG NickServ :INFO ns_owner");
+        other.expect(&*format!("{}ns_owner is registered to the account ns_owner", other_notice));
+        other.expect_begin(&*format!("{}Registered: ", other_notice));
+        other.send_msg("PRIVMSG NickServ :DROP");
+        other.expect(&*format!("{}You must be identified to use DROP.", other_notice));
+        other.send_msg("PRIVMSG NickServ :GROUP ns_owner hunter2");
+        other.expect(&*format!("{}You are now in the group of ns_owner.", other_notice));
+        other.send_msg("PRIVMSG NickServ :INFO");
+        other.expect(&*format!("{}ns_other is registered to the account ns_owner", other_notice));
+        other.expect_begin(&*format!("{}Registered: ", other_notice));
+        other.expect(&*format!("{}Email: owner@example.org", other_notice));
+        other.expect(&*format!("{}Nicks: ns_owner, ns_other", other_notice));
+        other.send_msg("WHOIS ns_other");
+        other.skip_until(":localhost 330 ns_other ns_other ns_owner :is logged in as");
+        other.skip_until(":localhost 318 ns_other ns_other");
+
+        owner.send_msg("PRIVMSG NickServ :DROP ns_other");
+        owner.expect(&*format!("{}Nick ns_other has been removed from your account.", notice));
+        owner.send_msg("PRIVMSG NickServ :DROP");
+        owner.expect(&*format!("{}Your account ns_owner has been dropped.", notice));
+        other.send_msg("PRIVMSG NickServ :IDENTIFY ns_owner hunter2");
+        other.expect(&*format!("{}Nick ns_owner isn't registered.", other_notice));
+    }
+
+    #[test]
+    fn login_failures() {
+        test::run_server();
+        let mut owner = test::Client::registered("ns_guarded");
+        owner.send_msg("PRIVMSG NickServ :REGISTER secret guard@example.com");
+        owner.skip_until(":NickServ!NickServ@localhost NOTICE ns_guarded :Nick ns_guarded has been registered");
+        let mut guesser = test::Client::registered("ns_guesser");
+        let notice = ":NickServ!NickServ@localhost NOTICE ns_guesser :";
+        for i in 0..4 {
+            guesser.send_msg(&*format!("PRIVMSG NickServ :IDENTIFY ns_guarded guess{}", i));
+            guesser.expect(&*format!("{}Invalid password for ns_guarded.", notice));
+        }
+        // GHOST, RECOVER and RELEASE count as well
+        guesser.send_msg("PRIVMSG NickServ :GHOST ns_guarded guess4");
+        guesser.expect("ERROR :Closing Link (Too many failed login attempts)");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn enforcement() {
+        test::run_server();
+        let mut owner = test::Client::registered("ns_enf");
+        let notice = ":NickServ!NickServ@localhost NOTICE ns_enf_a :";
+        owner.send_msg("PRIVMSG NickServ :REGISTER secret enf@example.com");
+        owner.skip_until(":NickServ!NickServ@localhost NOTICE ns_enf :Nick ns_enf has been registered");
+        owner.send_msg("NICK ns_enf_a");
+        owner.expect_begin(":ns_enf!");
+
+        // The nick is taken after the grace period and held afterwards
+        let mut squatter = test::Client::registered("ns_sq");
+        squatter.send_msg("NICK ns_enf");
+        squatter.expect_begin(":ns_sq!");
+        squatter.expect(":NickServ!NickServ@localhost NOTICE ns_enf :This nick is registered. \
+            Please identify via /msg NickServ IDENTIFY <password> within 3 seconds, \
+            otherwise your nick will be changed.");
+        squatter.expect_begin(":NickServ!NickServ@localhost NOTICE ns_enf :Your nick has been changed to Guest");
+        squatter.expect_begin(":ns_enf!");
+        squatter.send_msg("NICK ns_enf");
+        squatter.expect_begin(":localhost 437 Guest");
+        owner.send_msg("PRIVMSG NickServ :RELEASE ns_enf");
+        owner.expect(&*format!("{}Nick ns_enf has been released.", notice));
+        owner.send_msg("PRIVMSG NickServ :RELEASE ns_enf");
+        owner.expect(&*format!("{}Nick ns_enf is not being held.", notice));
+
+        // RECOVER and GHOST need the password or an identified client
+        squatter.send_msg("NICK ns_enf");
+        squatter.skip_until(":NickServ!NickServ@localhost NOTICE ns_enf :This nick is registered.");
+        let mut other = test::Client::registered("ns_enf_b");
+        other.send_msg("PRIVMSG NickServ :GHOST ns_enf wrong");
+        other.expect(":NickServ!NickServ@localhost NOTICE ns_enf_b :Access denied.");
+        owner.send_msg("PRIVMSG NickServ :RECOVER ns_enf");
+        owner.expect(&*format!("{}The user of ns_enf has been renamed, \
+            the nick is held for you for 60 seconds.", notice));
+        squatter.expect_begin(":NickServ!NickServ@localhost NOTICE ns_enf :Your nick has been changed to Guest");
+        squatter.expect_begin(":ns_enf!");
+        owner.send_msg("NICK ns_enf");
+        owner.expect_begin(":ns_enf_a!");
+        other.send_msg("PRIVMSG NickServ :GHOST ns_enf secret");
+        other.expect(":NickServ!NickServ@localhost NOTICE ns_enf_b :Ghost with your nick ns_enf has been killed.");
+        owner.expect("ERROR :Closing Link (GHOST command used by ns_enf_b)");
+        // The nick is free at once
+        other.send_msg("PRIVMSG NickServ :IDENTIFY ns_enf secret");
+        other.expect(":NickServ!NickServ@localhost NOTICE ns_enf_b :Password accepted - you are now identified for ns_enf.");
+        other.send_msg("NICK ns_enf");
+        other.expect_begin(":ns_enf_b!");
+    }
+}
@@ -1,17 +1,31 @@
 use std::io::prelude::*;
 use std::thread::spawn;
-use std::net::TcpStream;
+use std::net::{TcpStream, SocketAddr};
 use std::io::BufReader;
+use std::env;
+use std::fs::File;
 use server::Server;
+use config::TlsSettings;
 use std::sync::{Once, ONCE_INIT};
 
 
-pub use server::get_test_server;
+pub use server::{get_test_server, get_test_config};
+
+/// Address of the plain text listener of the test server
+pub const ADDR: &'static str = "127.0.0.1:6667";
+/// Address of the TLS listener of the test server
+pub const TLS_ADDR: &'static str = "127.0.0.1:6697";
+/// Address of the test server with short timeouts
+pub const TIMEOUT_ADDR: &'static str = "127.0.0.1:6668";
+
+/// Connection of a test client
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
 
 /// A test client that communicates with the server
 pub struct Client {
-	stream: TcpStream,
-	reader: BufReader<TcpStream>,
+	stream: BufReader<Box<Stream>>,
 	nick: String
 }
 
@@ -22,11 +36,33 @@ impl Client {
 		c
 	}
 	pub fn new(nick: String) -> Client {
-		let stream = TcpStream::connect("127.0.0.1:6667").unwrap();
-		let reader = BufReader::new(stream.try_clone().unwrap());
+		Client::connect(&nick, ADDR)
+	}
+	/// Connects to the given address of a test server
+	pub fn connect(nick: &str, addr: &str) -> Client {
+		Client::with_stream(nick.to_string(), Box::new(TcpStream::connect(addr).unwrap()))
+	}
+	/// Connects to the TLS listener
+	pub fn tls(nick: &str) -> Client {
+		use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+		let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+		// The test server uses a self-signed certificate
+		builder.set_verify(SslVerifyMode::NONE);
+		let stream = TcpStream::connect(TLS_ADDR).unwrap();
+		let stream = builder.build().connect("localhost", stream).unwrap();
+		Client::with_stream(nick.to_string(), Box::new(stream))
+	}
+	/// Connects from another local address
+	pub fn from_addr(nick: &str, addr: &str) -> Client {
+		use net2::TcpBuilder;
+		let builder = TcpBuilder::new_v4().unwrap();
+		builder.bind(SocketAddr::new(addr.parse().unwrap(), 0)).unwrap();
+		let stream = builder.connect(ADDR).unwrap();
+		Client::with_stream(nick.to_string(), Box::new(stream))
+	}
+	fn with_stream(nick: String, stream: Box<Stream>) -> Client {
 		Client {
-			stream: stream,
-			reader: reader,
+			stream: BufReader::new(stream),
 			nick: nick
 		}
 	}
@@ -35,18 +71,29 @@ impl Client {
 		self.send_msg("CAP END");
 		self.send_msg(&*format!("NICK {}", nick));
 		self.send_msg(&*format!("USER {} 0 * :Test user", nick));
-		self.expect_begin(&*format!(":localhost 001 {}", nick));
+		// Skips the notices of the host name lookup
+		self.skip_until(&*format!(":localhost 001 {}", nick));
+		self.skip_burst();
+	}
+	/// Skips the rest of the registration burst up to the end of the MOTD
+	pub fn skip_burst(&mut self) {
+		loop {
+			let m = self.read_msg();
+			if m.contains(" 376 ") || m.contains(" 422 ") {
+				break
+			}
+		}
 	}
 	pub fn send_msg(&mut self, msg: &str) {
 		self.send_raw(msg.as_bytes());
 		self.send_raw(b"\r\n");
 	}
 	pub fn send_raw(&mut self, msg: &[u8]) {
-		self.stream.write(msg).unwrap();
+		self.stream.get_mut().write_all(msg).unwrap();
 	}
 	pub fn read_raw(&mut self) -> Vec<u8> {
 		let mut buf = Vec::new();
-		self.reader.read_until(b'\n', &mut buf).unwrap();
+		self.stream.read_until(b'\n', &mut buf).unwrap();
 		let len = buf.len();
 		buf.truncate(len-2);
 		buf
@@ -54,6 +101,12 @@ impl Client {
 	pub fn read_msg(&mut self) -> String {
 		String::from_utf8(self.read_raw()).unwrap()
 	}
+	/// Expects the server to close the connection
+	pub fn expect_closed(&mut self) {
+		let mut buf = Vec::new();
+		let read = self.stream.read_until(b'\n', &mut buf).unwrap();
+		assert!(read == 0, "expected end of stream found {}", String::from_utf8_lossy(&buf))
+	}
 	pub fn expect(&mut self, msg: &str) {
 		assert_eq!(&*self.read_msg(), msg)
 	}
@@ -73,7 +126,38 @@ impl Client {
 	}
 }
 
+/// Writes a self-signed certificate for the TLS listener of the test server
+pub fn tls_settings() -> TlsSettings {
+	use openssl::asn1::Asn1Time;
+	use openssl::hash::MessageDigest;
+	use openssl::pkey::PKey;
+	use openssl::rsa::Rsa;
+	use openssl::x509::{X509, X509NameBuilder};
+	let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+	let mut name = X509NameBuilder::new().unwrap();
+	name.append_entry_by_text("CN", "localhost").unwrap();
+	let name = name.build();
+	let mut cert = X509::builder().unwrap();
+	cert.set_version(2).unwrap();
+	cert.set_subject_name(&name).unwrap();
+	cert.set_issuer_name(&name).unwrap();
+	cert.set_pubkey(&key).unwrap();
+	cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+	cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+	cert.sign(&key, MessageDigest::sha256()).unwrap();
+	let cert = cert.build();
+	let dir = env::temp_dir();
+	let settings = TlsSettings {
+		certificate: dir.join("rauta-test-cert.pem").to_string_lossy().into_owned(),
+		key: dir.join("rauta-test-key.pem").to_string_lossy().into_owned()
+	};
+	File::create(&settings.certificate).unwrap().write_all(&cert.to_pem().unwrap()).unwrap();
+	File::create(&settings.key).unwrap().write_all(&key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+	settings
+}
+
 static SERVER: Once = ONCE_INIT;
+static TIMEOUT_SERVER: Once = ONCE_INIT;
 
 pub fn run_server() {
 	SERVER.call_once(|| {
@@ -85,10 +169,29 @@ pub fn run_server() {
 		sleep_ms(1000);
 	});
 }
+
+/// Runs a second server with short timeouts on `TIMEOUT_ADDR`
+pub fn run_timeout_server() {
+	TIMEOUT_SERVER.call_once(|| {
+		use std::thread::sleep_ms;
+		use config::Listen;
+		let mut config = get_test_config();
+		config.listen = vec![Listen::plain(TIMEOUT_ADDR.parse().unwrap())];
+		config.io.registration_timeout = 2;
+		config.io.lookup_timeout = 1;
+		config.io.ping_frequency = 3;
+		config.io.ping_timeout = 1;
+		spawn(move || {
+			let mut server = Server::new(config).unwrap();
+			server.run_mio().unwrap();
+		});
+		sleep_ms(1000);
+	});
+}
 #[test]
 /// simple test for test infrastructure
 fn registration() {
 	run_server();
 	let mut client = Client::new("Nick".to_string());
 	client.register();
-}
\ No newline at end of file
+}
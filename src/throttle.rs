@@ -0,0 +1,237 @@
+//! Connection limits
+//!
+//! New connections are checked before they are handed to the client
+//! worker. The number of concurrent connections is limited per address and
+//! per network, the network of an address is given by the leading
+//! `ipv4_prefix` or `ipv6_prefix` bits. Additionally every address may only
+//! open `throttle_count` connections per `throttle_period`.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use net::Cidr;
+
+/// Number of throttle entries after which expired entries are dropped
+const PRUNE_LEN: usize = 1024;
+
+/// Connection limit settings
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Maximum number of concurrent connections per address
+    pub per_ip: usize,
+    /// Maximum number of concurrent connections per network
+    pub per_network: usize,
+    /// Prefix length of IPv4 networks
+    pub ipv4_prefix: u8,
+    /// Prefix length of IPv6 networks
+    pub ipv6_prefix: u8,
+    /// Number of connections an address may open per period
+    pub throttle_count: usize,
+    /// Seconds after which the throttle of an address is reset
+    pub throttle_period: u64,
+    /// Address ranges that are not limited
+    pub exempt: Vec<Cidr>
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            per_ip: 10,
+            per_network: 50,
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+            throttle_count: 10,
+            throttle_period: 60,
+            exempt: Vec::new()
+        }
+    }
+}
+
+/// Reason a connection has been refused
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    /// Too many connections from the address
+    Address,
+    /// Too many connections from the network
+    Network,
+    /// The address connected too often recently
+    Throttled
+}
+
+impl Refusal {
+    /// Reason that is sent to the client
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            Refusal::Address => "Too many connections from your host",
+            Refusal::Network => "Too many connections from your network",
+            Refusal::Throttled => "Connection throttled, try again later"
+        }
+    }
+}
+
+/// Tracks the connections per address and network
+#[derive(Debug)]
+pub struct Throttle {
+    settings: Settings,
+    addresses: HashMap<IpAddr, usize>,
+    networks: HashMap<Cidr, usize>,
+    /// Start of the current period and connections in it
+    recent: HashMap<IpAddr, (Instant, usize)>
+}
+
+impl Throttle {
+    pub fn new(settings: Settings) -> Throttle {
+        Throttle {
+            settings: settings,
+            addresses: HashMap::new(),
+            networks: HashMap::new(),
+            recent: HashMap::new()
+        }
+    }
+
+    /// Checks whether a new connection from `ip` is allowed
+    ///
+    /// Allowed connections are counted until `release` is called.
+    pub fn check(&mut self, ip: IpAddr, now: Instant) -> Result<(), Refusal> {
+        if !self.is_exempt(ip) {
+            let network = self.network(ip);
+            if self.addresses.get(&ip).map_or(0, |&v| v) >= self.settings.per_ip {
+                return Err(Refusal::Address)
+            }
+            if self.networks.get(&network).map_or(0, |&v| v) >= self.settings.per_network {
+                return Err(Refusal::Network)
+            }
+            if self.recent.len() > PRUNE_LEN {
+                self.prune(now)
+            }
+            let period = Duration::from_secs(self.settings.throttle_period);
+            let entry = self.recent.entry(ip).or_insert((now, 0));
+            if now.duration_since(entry.0) >= period {
+                *entry = (now, 0)
+            }
+            if entry.1 >= self.settings.throttle_count {
+                return Err(Refusal::Throttled)
+            }
+            entry.1 += 1;
+        }
+        // Exempt connections are counted as well in case the exemption
+        // changes with a rehash
+        *self.addresses.entry(ip).or_insert(0) += 1;
+        *self.networks.entry(self.network(ip)).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Releases a connection that has been allowed by `check`
+    pub fn release(&mut self, ip: IpAddr) {
+        let network = self.network(ip);
+        decrement(&mut self.addresses, ip);
+        decrement(&mut self.networks, network);
+    }
+
+    /// Number of concurrent connections from the address
+    pub fn connections(&self, ip: IpAddr) -> usize {
+        self.addresses.get(&ip).map_or(0, |&v| v)
+    }
+
+    /// Replaces the settings, the connections are kept
+    pub fn set_settings(&mut self, settings: Settings) {
+        let addresses: Vec<_> = self.addresses.iter().map(|(&ip, &n)| (ip, n)).collect();
+        self.settings = settings;
+        self.networks.clear();
+        for (ip, n) in addresses {
+            *self.networks.entry(self.network(ip)).or_insert(0) += n
+        }
+    }
+
+    fn is_exempt(&self, ip: IpAddr) -> bool {
+        self.settings.exempt.iter().any(|range| range.contains(ip))
+    }
+
+    fn network(&self, ip: IpAddr) -> Cidr {
+        match ip {
+            IpAddr::V4(_) => Cidr::new(ip, self.settings.ipv4_prefix),
+            IpAddr::V6(_) => Cidr::new(ip, self.settings.ipv6_prefix)
+        }
+    }
+
+    /// Drops the throttle entries of past periods
+    fn prune(&mut self, now: Instant) {
+        let period = Duration::from_secs(self.settings.throttle_period);
+        let expired: Vec<IpAddr> = self.recent.iter()
+            .filter(|&(_, &(start, _))| now.duration_since(start) >= period)
+            .map(|(&ip, _)| ip)
+            .collect();
+        for ip in expired {
+            self.recent.remove(&ip);
+        }
+    }
+}
+
+fn decrement<K: ::std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: K) {
+    let remove = match counts.get_mut(&key) {
+        Some(count) => {
+            *count = count.saturating_sub(1);
+            *count == 0
+        },
+        None => false
+    };
+    if remove {
+        counts.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use net::Cidr;
+    use super::{Throttle, Settings, Refusal};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn connection_limits() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(Settings {
+            per_ip: 2,
+            per_network: 3,
+            throttle_count: 100,
+            exempt: vec![Cidr::parse("10.1.0.0/16").unwrap()],
+            ..Default::default()
+        });
+        assert_eq!(throttle.check(ip("192.0.2.1"), now), Ok(()));
+        assert_eq!(throttle.check(ip("192.0.2.1"), now), Ok(()));
+        assert_eq!(throttle.check(ip("192.0.2.1"), now), Err(Refusal::Address));
+        assert_eq!(throttle.check(ip("192.0.2.2"), now), Ok(()));
+        assert_eq!(throttle.check(ip("192.0.2.3"), now), Err(Refusal::Network));
+        assert_eq!(throttle.check(ip("192.0.3.1"), now), Ok(()));
+        throttle.release(ip("192.0.2.1"));
+        assert_eq!(throttle.connections(ip("192.0.2.1")), 1);
+        assert_eq!(throttle.check(ip("192.0.2.3"), now), Ok(()));
+        throttle.release(ip("192.0.2.9"));
+        for _ in 0..5 {
+            assert_eq!(throttle.check(ip("10.1.2.3"), now), Ok(()));
+        }
+        assert_eq!(throttle.connections(ip("10.1.2.3")), 5);
+    }
+
+    #[test]
+    fn connection_throttle() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(Settings {
+            throttle_count: 2,
+            throttle_period: 10,
+            ..Default::default()
+        });
+        let addr = ip("2001:db8::1");
+        assert_eq!(throttle.check(addr, now), Ok(()));
+        throttle.release(addr);
+        assert_eq!(throttle.check(addr, now), Ok(()));
+        throttle.release(addr);
+        assert_eq!(throttle.check(addr, now), Err(Refusal::Throttled));
+        assert_eq!(throttle.check(addr, now + Duration::from_secs(10)), Ok(()));
+    }
+}
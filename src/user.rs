@@ -1,5 +1,13 @@
 //! User model
 use std::mem;
+use std::net::IpAddr;
+use std::time::Instant;
+use std::collections::HashSet;
+
+use time;
+
+use misc;
+use sasl;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Status {
@@ -21,6 +29,46 @@ pub const STATUS_NEG_NICKREG: Status = Status::Negotiating(&Status::NickRegister
 pub const STATUS_NEG_CONNECT: Status = Status::Negotiating(&Status::Connected);
 pub const STATUS_NEG_REG: Status = Status::Negotiating(&Status::Registered);
 
+/// User modes known to the server
+pub const USER_MODES: &'static str = "ioswx";
+
+/// Enumeration of the user modes
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
+pub enum UserMode {
+    /// Hides the user from WHO and NAMES of channels the requester is not in
+    Invisible = b'i' as isize,
+    /// IRC operator, can only be set with OPER
+    Operator = b'o' as isize,
+    /// Receives server notices, reserved for operators
+    ServerNotices = b's' as isize,
+    /// Receives WALLOPS messages
+    Wallops = b'w' as isize,
+    /// Shows a cloaked host name to other users
+    Cloaked = b'x' as isize,
+}
+
+impl UserMode {
+    /// Parses a user mode character
+    pub fn from_char(c: char) -> Option<UserMode> {
+        use self::UserMode::*;
+        match c {
+            'i' => Some(Invisible),
+            'o' => Some(Operator),
+            's' => Some(ServerNotices),
+            'w' => Some(Wallops),
+            'x' => Some(Cloaked),
+            _ => None
+        }
+    }
+
+    /// Character of the user mode
+    pub fn to_char(&self) -> char {
+        *self as u8 as char
+    }
+}
+/// Maximum length of an away message
+pub const AWAY_LEN: usize = 160;
+
 #[derive(Debug)]
 pub struct User {
     nick: String,
@@ -28,7 +76,23 @@ pub struct User {
     realname: String,
     host: String,
     status: Status,
-    hostmask: HostMask
+    registration_deadline: Option<Instant>,
+    secure: bool,
+    signon: i64,
+    last_active: i64,
+    away: Option<String>,
+    modes: HashSet<UserMode>,
+    cloaked_host: Option<String>,
+    ident: Option<String>,
+    ident_checked: bool,
+    ip: Option<IpAddr>,
+    account: Option<String>,
+    certfp: Option<String>,
+    capabilities: HashSet<String>,
+    sasl: Option<sasl::Session>,
+    login_failures: u32,
+    hostmask: HostMask,
+    public_hostmask: HostMask
 }
 
 impl User {
@@ -40,8 +104,24 @@ impl User {
             user: "".to_string(),
             realname: "John Doe".to_string(),
             host: host,
-            hostmask: mask,
-            status: Status::Connected
+            hostmask: mask.clone(),
+            public_hostmask: mask,
+            status: Status::Connected,
+            registration_deadline: None,
+            secure: false,
+            signon: 0,
+            last_active: 0,
+            away: None,
+            modes: HashSet::new(),
+            cloaked_host: None,
+            ident: None,
+            ident_checked: false,
+            ip: None,
+            account: None,
+            certfp: None,
+            capabilities: HashSet::new(),
+            sasl: None,
+            login_failures: 0
         }
     }
     
@@ -60,10 +140,22 @@ impl User {
         &*self.user
     }
     /// Getter for the user name
+    ///
+    /// The user name is replaced by the ident if one is known. Unverified
+    /// names are prefixed with `~` if the ident has been checked.
     pub fn set_user(&mut self, name: String) {
-        self.user = name;
+        self.user = match self.ident {
+            Some(ref ident) => ident.clone(),
+            None if self.ident_checked => format!("~{}", name),
+            None => name
+        };
         self.update_mask()
     }
+    /// Sets the result of the ident lookup
+    pub fn set_ident(&mut self, ident: Option<String>) {
+        self.ident = ident;
+        self.ident_checked = true
+    }
     /// Getter for the nick name
     pub fn realname(&self) -> &str {
         &*self.realname
@@ -77,21 +169,183 @@ impl User {
     pub fn host(&self) -> &str {
         &*self.host
     }
+    /// Setter for the host name
+    pub fn set_host(&mut self, host: String) {
+        self.host = host;
+        self.update_mask()
+    }
+    /// Getter for the address the user connected from
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+    /// Setter for the address the user connected from
+    pub fn set_ip(&mut self, ip: IpAddr) {
+        self.ip = Some(ip)
+    }
+    /// Getter for the NickServ account the user is identified to
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_ref().map(|v| &**v)
+    }
+    /// Setter for the NickServ account the user is identified to
+    pub fn set_account(&mut self, account: Option<String>) {
+        self.account = account
+    }
+    /// Getter for the number of failed password checks
+    pub fn login_failures(&self) -> u32 {
+        self.login_failures
+    }
+    /// Counts a failed password check, returns the number of failures
+    pub fn login_failed(&mut self) -> u32 {
+        self.login_failures += 1;
+        self.login_failures
+    }
+    /// Getter for the fingerprint of the TLS client certificate
+    pub fn certfp(&self) -> Option<&str> {
+        self.certfp.as_ref().map(|v| &**v)
+    }
+    /// Setter for the fingerprint of the TLS client certificate
+    pub fn set_certfp(&mut self, certfp: Option<String>) {
+        self.certfp = certfp
+    }
+    /// Checks whether the client enabled a capability
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.contains(capability)
+    }
+    /// Enables or disables a capability
+    pub fn set_capability(&mut self, capability: &str, enabled: bool) {
+        if enabled {
+            self.capabilities.insert(capability.to_string());
+        } else {
+            self.capabilities.remove(capability);
+        }
+    }
+    /// Returns the enabled capabilities in alphabetical order
+    pub fn capabilities(&self) -> Vec<&str> {
+        let mut capabilities: Vec<&str> = self.capabilities.iter().map(|v| &**v).collect();
+        capabilities.sort();
+        capabilities
+    }
+    /// Getter for the SASL authentication in progress
+    pub fn sasl_mut(&mut self) -> Option<&mut sasl::Session> {
+        self.sasl.as_mut()
+    }
+    /// Starts or ends a SASL authentication, returns the previous one
+    pub fn set_sasl(&mut self, session: Option<sasl::Session>) -> Option<sasl::Session> {
+        mem::replace(&mut self.sasl, session)
+    }
     /// Getter for the registration status/method
     pub fn status(&self) -> Status {
         self.status
     }
     /// Getter for the registration status/method
     pub fn set_status(&mut self, status: Status) {
+        if status == Status::Registered && self.status != Status::Registered {
+            self.registration_deadline = None;
+            self.signon = time::get_time().sec;
+            self.last_active = self.signon
+        }
         self.status = status
     }
+    /// Getter for the registration deadline
+    ///
+    /// The deadline is cleared as soon as the user is fully registered.
+    pub fn registration_deadline(&self) -> Option<Instant> {
+        self.registration_deadline
+    }
+    /// Setter for the registration deadline
+    pub fn set_registration_deadline(&mut self, deadline: Instant) {
+        if self.status != Status::Registered {
+            self.registration_deadline = Some(deadline)
+        }
+    }
+    /// Checks whether the user failed to register in time
+    pub fn registration_expired(&self, now: Instant) -> bool {
+        self.registration_deadline.map_or(false, |deadline| now >= deadline)
+    }
+    /// Getter for the time the user registered (seconds since the epoch)
+    pub fn signon(&self) -> i64 {
+        self.signon
+    }
+    /// Getter for the time of the last activity (seconds since the epoch)
+    pub fn last_active(&self) -> i64 {
+        self.last_active
+    }
+    /// Resets the idle time of the user
+    pub fn touch(&mut self) {
+        self.last_active = time::get_time().sec
+    }
+    /// Getter for the away message
+    pub fn away(&self) -> Option<&str> {
+        self.away.as_ref().map(|v| &**v)
+    }
+    /// Setter for the away message, `None` marks the user as present
+    pub fn set_away(&mut self, message: Option<String>) {
+        self.away = message
+    }
+    /// Checks whether the user has the mode set
+    pub fn has_mode(&self, mode: UserMode) -> bool {
+        self.modes.contains(&mode)
+    }
+    /// Sets or unsets a mode, returns whether the modes changed
+    pub fn set_mode(&mut self, mode: UserMode, set: bool) -> bool {
+        let changed = if set {
+            self.modes.insert(mode)
+        } else {
+            self.modes.remove(&mode)
+        };
+        if changed && mode == UserMode::Cloaked {
+            self.update_mask()
+        }
+        changed
+    }
+    /// User modes as a string, e.g. "+iw"
+    pub fn modes(&self) -> String {
+        let mut modes: Vec<char> = self.modes.iter().map(|mode| mode.to_char()).collect();
+        modes.sort();
+        Some('+').into_iter().chain(modes.into_iter()).collect()
+    }
+    /// Checks whether the user is an IRC operator
+    pub fn is_operator(&self) -> bool {
+        self.has_mode(UserMode::Operator)
+    }
+    /// Checks whether the user is connected via TLS
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+    /// Marks the connection of the user as secure
+    pub fn set_secure(&mut self, secure: bool) {
+        self.secure = secure
+    }
     
-    /// Updates the real hostmask
+    /// Getter for the cloaked host
+    pub fn cloaked_host(&self) -> Option<&str> {
+        self.cloaked_host.as_ref().map(|v| &**v)
+    }
+    /// Setter for the cloaked host, it is shown instead of the host
+    /// while the user has the mode `+x`
+    pub fn set_cloaked_host(&mut self, host: Option<String>) {
+        self.cloaked_host = host;
+        self.update_mask()
+    }
+    /// Getter for the host that is shown to other users
+    pub fn public_host(&self) -> &str {
+        match self.cloaked_host {
+            Some(ref cloak) if self.has_mode(UserMode::Cloaked) => &*cloak,
+            _ => &*self.host
+        }
+    }
+    
+    /// Updates the real and the public hostmask
     fn update_mask(&mut self) {
         self.hostmask = HostMask::from_parts(
             &*self.nick,
             &*self.user,
             &*self.host
+        );
+        self.public_hostmask = HostMask::from_parts(
+            &*self.nick,
+            &*self.user,
+            self.public_host()
         )
     }
 
@@ -99,7 +353,7 @@ impl User {
     ///
     /// This is the host mask that is send out to other users.
     pub fn public_hostmask(&self) -> &HostMask {
-        &self.hostmask
+        &self.public_hostmask
     }
     /// Getter for the real host mask
     pub fn real_hostmask(&self) -> &HostMask {
@@ -128,26 +382,7 @@ impl HostMask {
     ///
     /// "*!*@*.com" would match "a!b@example.com"
     pub fn matches(&self, mask: &str) -> bool {
-        let mut mask_chars = mask.chars().peekable();
-        let mut chars = self.mask.chars().peekable();
-        loop {
-            match chars.next() {
-                Some('*') => match chars.peek() {
-                    // Consume all chars until next match is found
-                    Some(next) => while match mask_chars.peek() {
-                        Some(mask_cha) => mask_cha != next,
-                        None => false } { let _ = mask_chars.next(); },
-                    // * at end of the string matches the whole rest
-                    None => return true
-                },
-                Some(cha) => match mask_chars.next() {
-                    None => return false,
-                    Some(mask_cha) => if cha != mask_cha { return false }
-                },
-                None => break
-            }
-        }
-        !mask_chars.next().is_some()
+        misc::match_mask(&self.mask, mask)
     }
     
     /// Returns the hostname
@@ -174,7 +409,8 @@ impl HostMask {
 
 #[cfg(test)]
 mod tests {
-    use super::HostMask;
+    use std::time::{Duration, Instant};
+    use super::{HostMask, User, Status, STATUS_NEG_REG};
     
     #[test]
     /// Test the hostname masks
@@ -189,5 +425,35 @@ mod tests {
         assert!(HostMask::new("*!bar@*.com".to_string()).matches("foo!bar@example.com"));
         assert!(!HostMask::new("*!bar@*.com".to_string()).matches("foo!baz@example.com"));
     }
+
+    #[test]
+    /// Test the registration deadline
+    fn registration_deadline() {
+        let now = Instant::now();
+        let mut user = User::new("localhost".to_string());
+        user.set_registration_deadline(now);
+        assert!(user.registration_expired(now));
+        assert!(!user.registration_expired(now - Duration::from_secs(1)));
+        user.set_status(STATUS_NEG_REG);
+        assert!(user.registration_expired(now));
+        user.set_status(Status::Registered);
+        assert!(!user.registration_expired(now));
+        assert_eq!(user.registration_deadline(), None);
+    }
+
+    #[test]
+    /// Test the user names with and without ident
+    fn ident_user_name() {
+        let mut user = User::new("localhost".to_string());
+        user.set_user("foo".to_string());
+        assert_eq!(user.user(), "foo");
+        user.set_ident(None);
+        user.set_user("foo".to_string());
+        assert_eq!(user.user(), "~foo");
+        user.set_ident(Some("bar".to_string()));
+        user.set_user("foo".to_string());
+        assert_eq!(user.user(), "bar");
+        assert!(HostMask::new("*!bar@localhost".to_string()).matches(user.real_hostmask().as_str()));
+    }
     
 }